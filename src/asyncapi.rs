@@ -0,0 +1,596 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use ginger_shared_rs::LANG;
+use serde_json::{json, Value as JsonValue};
+
+use crate::{
+    base_urls::BaseUrls,
+    file_utils::write_file,
+    services_toml::config_section_or_exit,
+    usage::{to_camel_case, to_snake_case},
+};
+
+/// Extension key under which a service embeds its AsyncAPI document in the published spec
+pub const ASYNCAPI_EXTENSION: &str = "x-ginger-asyncapi";
+
+/// Embeds the AsyncAPI document configured in services.toml into the spec about to be
+/// published:
+///
+/// ```toml
+/// [asyncapi]
+/// path = "asyncapi.json"
+/// ```
+pub fn attach_asyncapi(config_path: &Path, spec: &str) -> String {
+    let asyncapi_path = match config_section_or_exit(config_path, "asyncapi").and_then(|section| {
+        section
+            .get("path")
+            .and_then(|p| p.as_str().map(String::from))
+    }) {
+        Some(path) => path,
+        None => return spec.to_string(),
+    };
+
+    let document: JsonValue = match fs::read_to_string(&asyncapi_path)
+        .map_err(|e| format!("{:?}", e))
+        .and_then(|content| serde_json::from_str(&content).map_err(|e| format!("{:?}", e)))
+    {
+        Ok(document) => document,
+        Err(e) => {
+            eprintln!(
+                "Unable to read the AsyncAPI document {}: {}. Aborting metadata publishing.",
+                asyncapi_path, e
+            );
+            exit(1);
+        }
+    };
+
+    let mut spec_json: JsonValue = if spec.is_empty() {
+        json!({})
+    } else {
+        match serde_json::from_str(spec) {
+            Ok(value) => value,
+            Err(_) => return spec.to_string(),
+        }
+    };
+    if let Some(object) = spec_json.as_object_mut() {
+        object.insert(ASYNCAPI_EXTENSION.to_string(), document);
+    }
+    println!("Attached the AsyncAPI document from {}", asyncapi_path);
+    spec_json.to_string()
+}
+
+pub fn extract_asyncapi(spec: &str) -> Option<JsonValue> {
+    let spec_json: JsonValue = serde_json::from_str(spec).ok()?;
+    spec_json.get(ASYNCAPI_EXTENSION).cloned()
+}
+
+fn to_pascal_case(input: &str) -> String {
+    let camel = to_camel_case(&input.replace(['/', '.', '{', '}'], "_"));
+    let mut chars = camel.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Keywords that can't be used as field names as is, `r#` makes them raw identifiers
+const RUST_KEYWORDS: [&str; 51] = [
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+fn rust_field_name(field: &str) -> String {
+    let name = to_snake_case(field);
+    let name = match name.chars().next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => name,
+        _ => format!("field_{}", name),
+    };
+    match name.as_str() {
+        // Not allowed as raw identifiers
+        "self" | "super" | "crate" => format!("{}_", name),
+        _ if RUST_KEYWORDS.contains(&name.as_str()) => format!("r#{}", name),
+        _ => name,
+    }
+}
+
+/// Property names that aren't identifiers, like `content-type`, are quoted
+fn ts_property_name(field: &str) -> String {
+    let mut chars = field.chars();
+    let is_identifier = chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    match is_identifier {
+        true => field.to_string(),
+        false => serde_json::to_string(field).unwrap(),
+    }
+}
+
+fn resolve<'a>(doc: &'a JsonValue, value: &'a JsonValue) -> (&'a JsonValue, Option<String>) {
+    match value.get("$ref").and_then(|r| r.as_str()) {
+        Some(reference) => {
+            let name = reference.rsplit('/').next().map(|n| n.to_string());
+            match doc.pointer(reference.trim_start_matches('#')) {
+                Some(target) => (target, name),
+                None => (value, name),
+            }
+        }
+        None => (value, None),
+    }
+}
+
+struct Message {
+    name: String,
+    type_name: String,
+}
+
+struct Channel {
+    name: String,
+    path: String,
+    /// Messages the client sends, the `publish` operation of AsyncAPI 2
+    outgoing: Vec<Message>,
+    /// Messages the client receives, the `subscribe` operation of AsyncAPI 2
+    incoming: Vec<Message>,
+}
+
+/// Collects type definitions for the payload schemas, emitting each named type once
+trait TypeEmitter {
+    fn definitions(&mut self) -> &mut Vec<String>;
+    fn emitted(&mut self) -> &mut HashSet<String>;
+    fn primitive(&self, schema_type: &str) -> String;
+    fn array(&self, item: String) -> String;
+    fn object(&mut self, name: &str, schema: &JsonValue, doc: &JsonValue) -> String;
+
+    fn type_of(&mut self, doc: &JsonValue, schema: &JsonValue, suggested_name: &str) -> String {
+        let (schema, ref_name) = resolve(doc, schema);
+        let name = ref_name
+            .map(|n| to_pascal_case(&n))
+            .unwrap_or(to_pascal_case(suggested_name));
+
+        match schema.get("type").and_then(|t| t.as_str()) {
+            Some("array") => {
+                let item = match schema.get("items") {
+                    Some(items) => self.type_of(doc, items, &format!("{}Item", name)),
+                    None => self.primitive("any"),
+                };
+                self.array(item)
+            }
+            Some("object") | None if schema.get("properties").is_some() => {
+                if self.emitted().insert(name.clone()) {
+                    let definition = self.object(&name, schema, doc);
+                    self.definitions().push(definition);
+                }
+                name
+            }
+            Some(schema_type) => self.primitive(schema_type),
+            None => self.primitive("any"),
+        }
+    }
+}
+
+struct TsEmitter {
+    definitions: Vec<String>,
+    emitted: HashSet<String>,
+}
+
+impl TypeEmitter for TsEmitter {
+    fn definitions(&mut self) -> &mut Vec<String> {
+        &mut self.definitions
+    }
+
+    fn emitted(&mut self) -> &mut HashSet<String> {
+        &mut self.emitted
+    }
+
+    fn primitive(&self, schema_type: &str) -> String {
+        match schema_type {
+            "string" => "string",
+            "integer" | "number" => "number",
+            "boolean" => "boolean",
+            "object" => "Record<string, unknown>",
+            _ => "unknown",
+        }
+        .to_string()
+    }
+
+    fn array(&self, item: String) -> String {
+        format!("Array<{}>", item)
+    }
+
+    fn object(&mut self, name: &str, schema: &JsonValue, doc: &JsonValue) -> String {
+        let required: HashSet<&str> = schema
+            .get("required")
+            .and_then(|r| r.as_array())
+            .map(|r| r.iter().filter_map(|f| f.as_str()).collect())
+            .unwrap_or_default();
+        let mut fields = String::new();
+        if let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) {
+            for (field, field_schema) in properties {
+                let field_type = self.type_of(doc, field_schema, &format!("{}_{}", name, field));
+                let optional = if required.contains(field.as_str()) {
+                    ""
+                } else {
+                    "?"
+                };
+                fields.push_str(&format!(
+                    "  {}{}: {};\n",
+                    ts_property_name(field),
+                    optional,
+                    field_type
+                ));
+            }
+        }
+        format!("export interface {} {{\n{}}}\n", name, fields)
+    }
+}
+
+struct RustEmitter {
+    definitions: Vec<String>,
+    emitted: HashSet<String>,
+    /// Structs being emitted, a field referring to one of them is recursive and needs a `Box`
+    in_progress: Vec<String>,
+}
+
+impl TypeEmitter for RustEmitter {
+    fn definitions(&mut self) -> &mut Vec<String> {
+        &mut self.definitions
+    }
+
+    fn emitted(&mut self) -> &mut HashSet<String> {
+        &mut self.emitted
+    }
+
+    fn primitive(&self, schema_type: &str) -> String {
+        match schema_type {
+            "string" => "String",
+            "integer" => "i64",
+            "number" => "f64",
+            "boolean" => "bool",
+            _ => "serde_json::Value",
+        }
+        .to_string()
+    }
+
+    fn array(&self, item: String) -> String {
+        format!("Vec<{}>", item)
+    }
+
+    fn object(&mut self, name: &str, schema: &JsonValue, doc: &JsonValue) -> String {
+        let required: HashSet<&str> = schema
+            .get("required")
+            .and_then(|r| r.as_array())
+            .map(|r| r.iter().filter_map(|f| f.as_str()).collect())
+            .unwrap_or_default();
+        let mut fields = String::new();
+        self.in_progress.push(name.to_string());
+        if let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) {
+            for (field, field_schema) in properties {
+                let mut field_type =
+                    self.type_of(doc, field_schema, &format!("{}_{}", name, field));
+                if self.in_progress.contains(&field_type) {
+                    field_type = format!("Box<{}>", field_type);
+                }
+                let field_name = rust_field_name(field);
+                // serde strips the `r#` of raw identifiers
+                if field_name.trim_start_matches("r#") != field {
+                    fields.push_str(&format!("    #[serde(rename = \"{}\")]\n", field));
+                }
+                if required.contains(field.as_str()) {
+                    fields.push_str(&format!("    pub {}: {},\n", field_name, field_type));
+                } else {
+                    fields.push_str(
+                        "    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n",
+                    );
+                    fields.push_str(&format!(
+                        "    pub {}: Option<{}>,\n",
+                        field_name, field_type
+                    ));
+                }
+            }
+        }
+        self.in_progress.pop();
+        format!(
+            "#[derive(Debug, Clone, Serialize, Deserialize)]\npub struct {} {{\n{}}}\n",
+            name, fields
+        )
+    }
+}
+
+fn collect_messages(
+    doc: &JsonValue,
+    operation: Option<&JsonValue>,
+    fallback_name: &str,
+    emitter: &mut dyn TypeEmitter,
+) -> Vec<Message> {
+    let message = match operation.and_then(|op| op.get("message")) {
+        Some(message) => message,
+        None => return vec![],
+    };
+    let candidates: Vec<&JsonValue> = match message.get("oneOf").and_then(|m| m.as_array()) {
+        Some(messages) => messages.iter().collect(),
+        None => vec![message],
+    };
+
+    candidates
+        .into_iter()
+        .enumerate()
+        .map(|(index, candidate)| {
+            let (message, ref_name) = resolve(doc, candidate);
+            let name = message
+                .get("name")
+                .and_then(|n| n.as_str())
+                .map(|n| n.to_string())
+                .or(ref_name)
+                .unwrap_or(format!("{}{}", fallback_name, index));
+            let type_name = match message.get("payload") {
+                Some(payload) => emitter.type_of(doc, payload, &name),
+                None => emitter.primitive("any"),
+            };
+            Message {
+                name: to_pascal_case(&name),
+                type_name,
+            }
+        })
+        .collect()
+}
+
+fn collect_channels(doc: &JsonValue, emitter: &mut dyn TypeEmitter) -> Vec<Channel> {
+    let mut channels = vec![];
+    if let Some(entries) = doc.get("channels").and_then(|c| c.as_object()) {
+        for (path, channel) in entries {
+            let name = to_pascal_case(path.trim_matches('/'));
+            let outgoing = collect_messages(
+                doc,
+                channel.get("publish"),
+                &format!("{}Outgoing", name),
+                emitter,
+            );
+            let incoming = collect_messages(
+                doc,
+                channel.get("subscribe"),
+                &format!("{}Incoming", name),
+                emitter,
+            );
+            let path = if path.starts_with('/') {
+                path.clone()
+            } else {
+                format!("/{}", path)
+            };
+            channels.push(Channel {
+                name,
+                path,
+                outgoing,
+                incoming,
+            });
+        }
+    }
+    channels
+}
+
+fn render_ts(doc: &JsonValue, base_urls: &str) -> String {
+    let mut emitter = TsEmitter {
+        definitions: vec![],
+        emitted: HashSet::new(),
+    };
+    let channels = collect_channels(doc, &mut emitter);
+
+    let mut content = String::from("/* tslint:disable */\n/* eslint-disable */\n\n");
+    content.push_str(base_urls);
+    content.push('\n');
+    for definition in &emitter.definitions {
+        content.push_str(definition);
+        content.push('\n');
+    }
+
+    for channel in &channels {
+        let incoming_type = if channel.incoming.is_empty() {
+            "unknown".to_string()
+        } else {
+            channel
+                .incoming
+                .iter()
+                .map(|m| m.type_name.clone())
+                .collect::<Vec<String>>()
+                .join(" | ")
+        };
+        content.push_str(&format!(
+            "export type {name}Incoming = {incoming};\n\nexport class {name}Channel {{\n  private socket: WebSocket;\n\n  constructor(baseUrl: string = getBaseUrl()) {{\n    this.socket = new WebSocket(`${{baseUrl}}{path}`);\n  }}\n\n  ready(): Promise<void> {{\n    if (this.socket.readyState === WebSocket.OPEN) {{\n      return Promise.resolve();\n    }}\n    return new Promise((resolve, reject) => {{\n      this.socket.addEventListener('open', () => resolve(), {{ once: true }});\n      this.socket.addEventListener('error', (event) => reject(event), {{ once: true }});\n    }});\n  }}\n\n",
+            name = channel.name,
+            incoming = incoming_type,
+            path = channel.path
+        ));
+        for message in &channel.outgoing {
+            content.push_str(&format!(
+                "  send{name}(message: {ty}): void {{\n    this.socket.send(JSON.stringify(message));\n  }}\n\n",
+                name = message.name,
+                ty = message.type_name
+            ));
+        }
+        content.push_str(&format!(
+            "  onMessage(handler: (message: {name}Incoming) => void): void {{\n    this.socket.addEventListener('message', (event) => handler(JSON.parse(event.data)));\n  }}\n\n  close(): void {{\n    this.socket.close();\n  }}\n}}\n\n",
+            name = channel.name
+        ));
+    }
+    content
+}
+
+fn render_rust(doc: &JsonValue, base_urls: &str) -> String {
+    let mut emitter = RustEmitter {
+        definitions: vec![],
+        emitted: HashSet::new(),
+        in_progress: vec![],
+    };
+    let channels = collect_channels(doc, &mut emitter);
+
+    let mut content = String::from(
+        r#"use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite, MaybeTlsStream, WebSocketStream};
+
+"#,
+    );
+    content.push_str(base_urls);
+    content.push('\n');
+    content.push_str(
+        r#"#[derive(Debug)]
+pub enum WsError {
+    Transport(tungstenite::Error),
+    Decode(serde_json::Error),
+}
+
+"#,
+    );
+
+    for definition in &emitter.definitions {
+        content.push_str(definition);
+        content.push('\n');
+    }
+
+    for channel in &channels {
+        let variants: String = channel
+            .incoming
+            .iter()
+            .map(|m| format!("    {}({}),\n", m.name, m.type_name))
+            .collect();
+        content.push_str(&format!(
+            "#[derive(Debug, Clone, Serialize, Deserialize)]\n#[serde(untagged)]\npub enum {name}Incoming {{\n{variants}    Other(serde_json::Value),\n}}\n\n",
+            name = channel.name,
+            variants = variants
+        ));
+
+        content.push_str(&format!(
+            r#"pub struct {name}Channel {{
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}}
+
+impl {name}Channel {{
+    pub async fn connect() -> Result<Self, WsError> {{
+        Self::connect_to(&base_url()).await
+    }}
+
+    pub async fn connect_to(base_url: &str) -> Result<Self, WsError> {{
+        let (stream, _) = connect_async(format!("{{}}{path}", base_url))
+            .await
+            .map_err(WsError::Transport)?;
+        Ok(Self {{ stream }})
+    }}
+
+    pub async fn next_message(&mut self) -> Option<Result<{name}Incoming, WsError>> {{
+        loop {{
+            match self.stream.next().await? {{
+                Ok(tungstenite::Message::Text(text)) => {{
+                    return Some(serde_json::from_str(&text).map_err(WsError::Decode))
+                }}
+                Ok(tungstenite::Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(e) => return Some(Err(WsError::Transport(e))),
+            }}
+        }}
+    }}
+
+    pub async fn close(&mut self) -> Result<(), WsError> {{
+        self.stream.close(None).await.map_err(WsError::Transport)
+    }}
+"#,
+            name = channel.name,
+            path = channel.path
+        ));
+
+        for message in &channel.outgoing {
+            content.push_str(&format!(
+                r#"
+    pub async fn send_{fn_name}(&mut self, message: &{ty}) -> Result<(), WsError> {{
+        let text = serde_json::to_string(message).map_err(WsError::Decode)?;
+        self.stream
+            .send(tungstenite::Message::Text(text))
+            .await
+            .map_err(WsError::Transport)
+    }}
+"#,
+                fn_name = to_snake_case(&message.name),
+                ty = message.type_name
+            ));
+        }
+        content.push_str("}\n\n");
+    }
+    content
+}
+
+/// Generates a typed websocket client next to the REST client of the service, its url is
+/// resolved at runtime from `GINGER_<SERVICE>_WS_URL` and the `ws_refs` defaults
+pub fn generate_ws_client(
+    document: &JsonValue,
+    service_name: &str,
+    lang: LANG,
+    root_dir: &str,
+    ws_urls: &BaseUrls,
+) {
+    if document
+        .get("asyncapi")
+        .and_then(|v| v.as_str())
+        .map_or(true, |version| !version.starts_with('2'))
+    {
+        println!(
+            "Only AsyncAPI 2.x documents are supported, skipping the websocket client for {}",
+            service_name
+        );
+        return;
+    }
+
+    let output_dir = PathBuf::from(format!("{}/{}_ws", root_dir, service_name));
+    let crate_name = format!("{}_ws", service_name);
+    match lang {
+        LANG::TS => {
+            write_file(
+                &output_dir.join("index.ts"),
+                &render_ts(document, &ws_urls.render_ts(&crate_name)),
+            );
+        }
+        LANG::Rust => {
+            write_file(
+                &output_dir.join("Cargo.toml"),
+                &format!(
+                    r#"[package]
+name = "{crate_name}"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+futures-util = "0.3"
+serde = {{ version = "1", features = ["derive"] }}
+serde_json = "1"
+tokio = {{ version = "1", features = ["net"] }}
+tokio-tungstenite = {{ version = "0.21", features = ["native-tls"] }}
+"#,
+                    crate_name = crate_name
+                ),
+            );
+            write_file(
+                &output_dir.join("src/lib.rs"),
+                &render_rust(document, &ws_urls.render_rust(&crate_name)),
+            );
+            println!(
+                "please add \n\n{} = {{ path = \"{}\" }}\n\n in cargo.toml file if not added",
+                crate_name,
+                output_dir.display()
+            );
+        }
+        LANG::Python | LANG::Shell => {
+            println!(
+                "Websocket client generation is not supported for {}, skipping {}",
+                lang, service_name
+            );
+            return;
+        }
+    }
+    println!("Websocket client generated in {}", output_dir.display());
+}
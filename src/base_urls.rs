@@ -0,0 +1,279 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::environments::{EnvKey, Environments};
+
+/// Base urls baked into a generated client as defaults. At runtime the client resolves, in
+/// order: the url set through the override API, `GINGER_<SERVICE>_URL`, the default for
+/// `GINGER_ENV` and finally the default for the env it was generated for.
+pub struct BaseUrls {
+    pub default_env: String,
+    pub defaults: BTreeMap<String, String>,
+}
+
+impl BaseUrls {
+    /// Defaults for every known env the service has a url for (following the fallbacks),
+    /// `base_url` being the one `connect` resolved for `env`. Preview urls are left out unless
+    /// generating for a preview: `{preview_id}` is only known once deployed
+    pub fn collect(
+        environments: &Environments,
+        service_urls: &HashMap<String, String>,
+        env: &EnvKey,
+        base_url: &str,
+    ) -> BaseUrls {
+        let mut defaults: BTreeMap<String, String> = environments
+            .names()
+            .into_iter()
+            .filter(|name| !EnvKey::new(name).is_preview())
+            .filter_map(|name| {
+                environments
+                    .resolve(service_urls, &EnvKey::new(&name))
+                    .ok()
+                    .map(|url| (name, url.clone()))
+            })
+            .collect();
+        defaults.insert(env.to_string(), base_url.to_string());
+
+        BaseUrls {
+            default_env: env.to_string(),
+            defaults,
+        }
+    }
+
+    /// `GINGER_IAMSERVICE_URL` for `IAMService`
+    pub fn env_var(service_name: &str) -> String {
+        let name: String = service_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("GINGER_{}_URL", name)
+    }
+
+    fn defaults_literal(&self) -> String {
+        serde_json::to_string_pretty(&self.defaults).unwrap()
+    }
+
+    /// Appended to the `lib.rs` of generated crates, exposes `base_url()` and `set_base_url()`
+    pub fn render_rust(&self, service_name: &str) -> String {
+        let defaults: String = self
+            .defaults
+            .iter()
+            .map(|(env, url)| format!("        ({:?}, {:?}),\n", env, url))
+            .collect();
+        format!(
+            r#"
+    pub const BASE_URL_ENV_VAR: &str = "{env_var}";
+    pub const DEFAULT_ENV: &str = "{default_env}";
+    pub const DEFAULT_BASE_URLS: &[(&str, &str)] = &[
+{defaults}    ];
+
+    static BASE_URL_OVERRIDE: std::sync::RwLock<Option<String>> = std::sync::RwLock::new(None);
+
+    /// Overrides the base url for every configuration created afterwards
+    pub fn set_base_url(url: impl Into<String>) {{
+        *BASE_URL_OVERRIDE.write().unwrap() = Some(url.into());
+    }}
+
+    fn default_base_url(env: &str) -> Option<String> {{
+        DEFAULT_BASE_URLS
+            .iter()
+            .find(|(name, _)| *name == env)
+            .map(|(_, url)| url.to_string())
+    }}
+
+    /// The override, then `{env_var}`, then the default url of `GINGER_ENV`
+    pub fn base_url() -> String {{
+        if let Some(url) = BASE_URL_OVERRIDE.read().unwrap().clone() {{
+            return url;
+        }}
+        if let Some(url) = std::env::var(BASE_URL_ENV_VAR).ok().filter(|url| !url.is_empty()) {{
+            return url;
+        }}
+        std::env::var("GINGER_ENV")
+            .ok()
+            .and_then(|env| default_base_url(&env))
+            .or_else(|| default_base_url(DEFAULT_ENV))
+            .unwrap_or_default()
+    }}
+"#,
+            env_var = Self::env_var(service_name),
+            default_env = self.default_env,
+            defaults = defaults
+        )
+    }
+
+    /// Declares `getBaseUrl()` and `setBaseUrl()` in the generated `index.ts`
+    pub fn render_ts(&self, service_name: &str) -> String {
+        format!(
+            r#"export const BASE_URL_ENV_VAR = '{env_var}';
+export const DEFAULT_ENV = '{default_env}';
+export const DEFAULT_BASE_URLS: Record<string, string> = {defaults};
+
+let baseUrlOverride: string | null = null;
+
+/** Overrides the base url of every request, pass null to go back to the defaults */
+export const setBaseUrl = (url: string | null): void => {{
+    baseUrlOverride = url;
+}};
+
+// Only read in node: bundlers inline static `process.env.NAME` references, not this lookup,
+// so browser builds rely on setBaseUrl or on the default of their env
+const readEnv = (name: string): string | undefined =>
+    (globalThis as any).process?.env?.[name] || undefined;
+
+/** The override, then `{env_var}`, then the default url of `GINGER_ENV` */
+export const getBaseUrl = (): string =>
+    baseUrlOverride ??
+    readEnv(BASE_URL_ENV_VAR) ??
+    DEFAULT_BASE_URLS[readEnv('GINGER_ENV') ?? DEFAULT_ENV] ??
+    DEFAULT_BASE_URLS[DEFAULT_ENV];
+"#,
+            env_var = Self::env_var(service_name),
+            default_env = self.default_env,
+            defaults = self.defaults_literal()
+        )
+    }
+
+    /// Declares `get_base_url()` and `set_base_url()` in the generated `config_utils.py`
+    pub fn render_python(&self, service_name: &str) -> String {
+        format!(
+            r#"import os
+
+BASE_URL_ENV_VAR = "{env_var}"
+DEFAULT_ENV = "{default_env}"
+DEFAULT_BASE_URLS = {defaults}
+
+_base_url_override = None
+
+
+def set_base_url(url):
+    """Overrides the base url of the configurations created afterwards, None resets it"""
+    global _base_url_override
+    _base_url_override = url
+
+
+def get_base_url():
+    """The override, then {env_var}, then the default url of GINGER_ENV"""
+    if _base_url_override:
+        return _base_url_override
+    if os.environ.get(BASE_URL_ENV_VAR):
+        return os.environ[BASE_URL_ENV_VAR]
+    env = os.environ.get("GINGER_ENV", DEFAULT_ENV)
+    return DEFAULT_BASE_URLS.get(env, DEFAULT_BASE_URLS.get(DEFAULT_ENV, ""))
+"#,
+            env_var = Self::env_var(service_name),
+            default_env = self.default_env,
+            defaults = self.defaults_literal()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn iam_urls(env: &EnvKey, base_url: &str) -> BaseUrls {
+        let service_urls: HashMap<String, String> = [
+            ("dev", "http://localhost:8080/iam"),
+            ("stage", "https://stage.example.com/iam"),
+            ("prod", "https://example.com/iam"),
+            ("preview", "https://pr-{preview_id}.example.com/iam"),
+        ]
+        .iter()
+        .map(|(env, url)| (env.to_string(), url.to_string()))
+        .collect();
+        let environments = Environments::load(Path::new("no/such/dir/services.toml"));
+        BaseUrls::collect(&environments, &service_urls, env, base_url)
+    }
+
+    fn stage() -> BaseUrls {
+        iam_urls(&EnvKey::new("stage"), "https://stage.example.com/iam")
+    }
+
+    #[test]
+    fn defaults_follow_the_fallbacks_and_leave_previews_out() {
+        let urls = stage();
+        assert_eq!(urls.default_env, "stage");
+        assert_eq!(
+            urls.defaults,
+            BTreeMap::from([
+                ("dev".to_string(), "http://localhost:8080/iam".to_string()),
+                ("prod".to_string(), "https://example.com/iam".to_string()),
+                ("prod_k8".to_string(), "https://example.com/iam".to_string()),
+                (
+                    "stage".to_string(),
+                    "https://stage.example.com/iam".to_string()
+                ),
+                (
+                    "stage_k8".to_string(),
+                    "https://stage.example.com/iam".to_string()
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn a_preview_client_defaults_to_its_expanded_url() {
+        let env = EnvKey::preview("PR-12");
+        let urls = iam_urls(&env, "https://pr-PR-12.example.com/iam");
+        assert_eq!(urls.default_env, "preview-PR-12");
+        assert_eq!(
+            urls.defaults.get("preview-PR-12").map(String::as_str),
+            Some("https://pr-PR-12.example.com/iam")
+        );
+        assert!(!urls.defaults.contains_key("preview"));
+        assert!(urls
+            .defaults
+            .values()
+            .all(|url| !url.contains("{preview_id}")));
+    }
+
+    #[test]
+    fn env_vars_are_derived_from_the_service_name() {
+        assert_eq!(BaseUrls::env_var("IAMService"), "GINGER_IAMSERVICE_URL");
+        assert_eq!(BaseUrls::env_var("Orders_ws"), "GINGER_ORDERS_WS_URL");
+    }
+
+    #[test]
+    fn rust_clients_embed_the_defaults() {
+        let rendered = stage().render_rust("IAMService");
+        assert!(rendered.contains(r#"pub const BASE_URL_ENV_VAR: &str = "GINGER_IAMSERVICE_URL";"#));
+        assert!(rendered.contains(r#"pub const DEFAULT_ENV: &str = "stage";"#));
+        assert!(rendered.contains(
+            "    pub const DEFAULT_BASE_URLS: &[(&str, &str)] = &[\n        (\"dev\", \"http://localhost:8080/iam\"),\n"
+        ));
+        assert!(rendered.contains(r#"        ("stage_k8", "https://stage.example.com/iam"),"#));
+        assert!(rendered.contains("pub fn set_base_url(url: impl Into<String>) {"));
+        assert!(!rendered.contains("{{"));
+    }
+
+    #[test]
+    fn ts_clients_embed_the_defaults() {
+        let rendered = stage().render_ts("IAMService");
+        assert!(rendered.starts_with("export const BASE_URL_ENV_VAR = 'GINGER_IAMSERVICE_URL';\nexport const DEFAULT_ENV = 'stage';\n"));
+        assert!(rendered.contains(
+            "export const DEFAULT_BASE_URLS: Record<string, string> = {\n  \"dev\": \"http://localhost:8080/iam\",\n"
+        ));
+        assert!(rendered.contains("export const setBaseUrl = (url: string | null): void => {\n"));
+        assert!(!rendered.contains("{{"));
+    }
+
+    #[test]
+    fn python_clients_embed_the_defaults() {
+        let rendered = stage().render_python("IAMService");
+        assert!(rendered
+            .contains("BASE_URL_ENV_VAR = \"GINGER_IAMSERVICE_URL\"\nDEFAULT_ENV = \"stage\"\n"));
+        assert!(
+            rendered.contains("DEFAULT_BASE_URLS = {\n  \"dev\": \"http://localhost:8080/iam\",\n")
+        );
+        assert!(rendered.contains("    \"\"\"The override, then GINGER_IAMSERVICE_URL, then the default url of GINGER_ENV\"\"\"\n"));
+        assert!(!rendered.contains("{{"));
+    }
+}
@@ -0,0 +1,380 @@
+use std::{path::Path, process::exit};
+
+use colored::Colorize;
+use ginger_shared_rs::utils::split_slug;
+use serde_json::Value as JsonValue;
+use MetadataService::apis::{
+    configuration::Configuration as MetadataConfiguration,
+    default_api::{
+        metadata_get_service_and_env_by_id, metadata_get_services_and_envs,
+        MetadataGetServiceAndEnvByIdParams, MetadataGetServicesAndEnvsParams,
+    },
+};
+
+use crate::{
+    contract::{published_contract_kind, ContractKind},
+    pagination::{collect_pages, PAGE_SIZE},
+    pipeline_provider::{Pipeline, PIPELINE_LINK_LABEL},
+    services_toml::read_services_config,
+    spec_lint::HTTP_METHODS,
+};
+
+/// Envs whose published spec is preferred when summarizing the endpoints of a service
+const SPEC_ENVS: [&str; 3] = ["stage", "prod", "dev"];
+
+/// What the catalogue shows of a service, read from the typed metadata models
+struct CatalogueEntry {
+    organization_id: String,
+    identifier: String,
+    description: Option<String>,
+    owners: Vec<String>,
+    service_type: Option<String>,
+    repo_origin: Option<String>,
+    quick_links: Option<String>,
+    envs: Vec<EnvUrls>,
+}
+
+struct EnvUrls {
+    env_key: String,
+    base_url: String,
+    base_url_ws: Option<String>,
+    /// Filled by `load_env_details`, the list of services doesn't carry them
+    version: Option<String>,
+    spec: Option<String>,
+}
+
+impl CatalogueEntry {
+    fn slug(&self) -> String {
+        format!("@{}/{}", self.organization_id, self.identifier)
+    }
+
+    fn service_type(&self) -> &str {
+        self.service_type.as_deref().unwrap_or("Unknown")
+    }
+}
+
+async fn fetch_catalogue(
+    metadata_config: &MetadataConfiguration,
+    org_id: &str,
+) -> Vec<CatalogueEntry> {
+    match collect_pages(|page| {
+        metadata_get_services_and_envs(
+            metadata_config,
+            MetadataGetServicesAndEnvsParams {
+                page_number: Some(page.to_string()),
+                page_size: Some(PAGE_SIZE.to_string()),
+                org_id: org_id.to_string(),
+            },
+        )
+    })
+    .await
+    {
+        Ok(services) => services
+            .into_iter()
+            .map(|service| CatalogueEntry {
+                organization_id: service.organization_id,
+                identifier: service.identifier,
+                description: Some(service.description).filter(|d| !d.is_empty()),
+                owners: service.owners.flatten().unwrap_or_default(),
+                service_type: service.service_type.flatten(),
+                repo_origin: service.repo_origin.flatten(),
+                quick_links: service.quick_links.flatten(),
+                envs: service
+                    .envs
+                    .into_iter()
+                    .map(|env| EnvUrls {
+                        env_key: env.env_key,
+                        base_url: env.base_url,
+                        base_url_ws: env.base_url_ws.flatten().filter(|ws| !ws.is_empty()),
+                        version: None,
+                        spec: None,
+                    })
+                    .collect(),
+            })
+            .collect(),
+        Err(e) => {
+            println!("{:?}", e);
+            println!("Unable to get the services of {}", org_id);
+            exit(1);
+        }
+    }
+}
+
+fn text<'a>(value: &'a JsonValue, key: &str) -> Option<&'a str> {
+    value
+        .get(key)
+        .and_then(|v| v.as_str())
+        .filter(|v| !v.is_empty())
+}
+
+fn org_of_project(config_path: &Path) -> String {
+    match read_services_config(config_path) {
+        Ok(config) => config.organization_id,
+        Err(e) => {
+            println!("{:?}", e);
+            println!(
+                "There is no service configuration found. Please use {} to add one. Exiting",
+                "ginger-connector init".blue()
+            );
+            exit(1);
+        }
+    }
+}
+
+/// Lists the services whose slug, type or description contains the query (case insensitive)
+pub async fn search_services(
+    config_path: &Path,
+    metadata_config: &MetadataConfiguration,
+    query: &str,
+) {
+    let org_id = org_of_project(config_path);
+    let query = query.to_lowercase();
+
+    let mut matches: Vec<CatalogueEntry> = fetch_catalogue(metadata_config, &org_id)
+        .await
+        .into_iter()
+        .filter(|service| {
+            [
+                service.slug(),
+                service.service_type().to_string(),
+                service.description.clone().unwrap_or_default(),
+            ]
+            .iter()
+            .any(|field| field.to_lowercase().contains(&query))
+        })
+        .collect();
+    matches.sort_by_key(CatalogueEntry::slug);
+
+    if matches.is_empty() {
+        println!("No services matching {:?} in {}", query, org_id);
+        return;
+    }
+
+    for service in &matches {
+        println!("{} ({})", service.slug().bold(), service.service_type());
+        if let Some(description) = &service.description {
+            println!("  {}", description);
+        }
+    }
+}
+
+fn print_quick_links(service: &CatalogueEntry) {
+    // Published as the JSON encoded `links` of metadata.toml
+    let links: Vec<JsonValue> = match service
+        .quick_links
+        .as_deref()
+        .and_then(|l| serde_json::from_str(l).ok())
+    {
+        Some(JsonValue::Array(links)) => links
+            .into_iter()
+            .filter(|link| text(link, "label") != Some(PIPELINE_LINK_LABEL))
+            .collect(),
+        _ => return,
+    };
+    if links.is_empty() {
+        return;
+    }
+    println!("{}", "Quick links:".bold());
+    for link in &links {
+        println!(
+            "  {} : {}",
+            text(link, "label").unwrap_or("-"),
+            text(link, "link").unwrap_or("-")
+        );
+    }
+}
+
+fn print_envs(service: &CatalogueEntry) {
+    if service.envs.is_empty() {
+        return;
+    }
+    println!("{}", "Envs:".bold());
+    for env in &service.envs {
+        match &env.version {
+            Some(version) => println!("  {} ({}) : {}", env.env_key, version, env.base_url),
+            None => println!("  {} : {}", env.env_key, env.base_url),
+        }
+        if let Some(ws) = &env.base_url_ws {
+            println!("    ws : {}", ws);
+        }
+    }
+}
+
+fn summarize_openapi(spec: &JsonValue) {
+    let paths = match spec.get("paths").and_then(|p| p.as_object()) {
+        Some(paths) => paths,
+        None => return,
+    };
+    println!("{} ({} paths)", "Endpoints:".bold(), paths.len());
+    for (path, item) in paths {
+        for method in HTTP_METHODS {
+            if let Some(operation) = item.get(method) {
+                let description = text(operation, "summary")
+                    .or(text(operation, "operationId"))
+                    .unwrap_or("");
+                println!(
+                    "  {:7} {} {}",
+                    method.to_uppercase(),
+                    path,
+                    description.dimmed()
+                );
+            }
+        }
+    }
+}
+
+fn summarize_grpc(spec: &JsonValue) {
+    let protos = match spec.get("protos").and_then(|p| p.as_object()) {
+        Some(protos) => protos,
+        None => return,
+    };
+    println!("{}", "RPCs:".bold());
+    for (file, content) in protos {
+        println!("  {}", file);
+        for line in content.as_str().unwrap_or_default().lines() {
+            let line = line.trim();
+            if line.starts_with("service ") || line.starts_with("rpc ") {
+                println!("    {}", line.trim_end_matches('{').trim());
+            }
+        }
+    }
+}
+
+fn summarize_graphql(spec: &JsonValue) {
+    println!(
+        "{} {}",
+        "GraphQL endpoint:".bold(),
+        text(spec, "endpoint_path").unwrap_or("/graphql")
+    );
+    let schema = text(spec, "schema").unwrap_or_default();
+    let mut root_type: Option<&str> = None;
+    for line in schema.lines() {
+        let line = line.trim();
+        if let Some(name) = ["type Query", "type Mutation", "type Subscription"]
+            .iter()
+            .find(|root| line.starts_with(*root))
+        {
+            root_type = Some(name.trim_start_matches("type "));
+            println!("  {}", root_type.unwrap());
+        } else if line.starts_with('}') {
+            root_type = None;
+        } else if root_type.is_some() && !line.is_empty() && !line.starts_with('#') {
+            println!("    {}", line);
+        }
+    }
+}
+
+/// Reads the version and spec published in every env of the service
+async fn load_env_details(metadata_config: &MetadataConfiguration, service: &mut CatalogueEntry) {
+    for env in service.envs.iter_mut() {
+        match metadata_get_service_and_env_by_id(
+            metadata_config,
+            MetadataGetServiceAndEnvByIdParams {
+                service_identifier: service.identifier.clone(),
+                env: env.env_key.clone(),
+                org_id: service.organization_id.clone(),
+            },
+        )
+        .await
+        {
+            Ok(response) => {
+                env.version = response.version.flatten().filter(|v| !v.is_empty());
+                env.spec = Some(response.spec);
+            }
+            Err(e) => println!("Unable to fetch the details of {}: {:?}", env.env_key, e),
+        }
+    }
+}
+
+fn summarize_spec(service: &CatalogueEntry) {
+    let published_envs: Vec<&str> = service
+        .envs
+        .iter()
+        .map(|env| env.env_key.as_str())
+        .collect();
+    let env = match SPEC_ENVS
+        .iter()
+        .find(|env| published_envs.contains(env))
+        .copied()
+        .or(published_envs.first().copied())
+    {
+        Some(env) => env,
+        None => return,
+    };
+
+    let spec = service
+        .envs
+        .iter()
+        .find(|e| e.env_key == env)
+        .and_then(|e| e.spec.clone())
+        .unwrap_or_default();
+    let kind = published_contract_kind(&spec);
+    let spec: JsonValue = match serde_json::from_str(&spec) {
+        Ok(spec) => spec,
+        Err(_) => {
+            println!("No spec published in {}", env);
+            return;
+        }
+    };
+
+    println!("{} {} (from {})", "Contract:".bold(), kind, env);
+    match kind {
+        ContractKind::OpenApi => summarize_openapi(&spec),
+        ContractKind::Grpc => summarize_grpc(&spec),
+        ContractKind::GraphQL => summarize_graphql(&spec),
+    }
+}
+
+pub async fn describe_service(metadata_config: &MetadataConfiguration, slug: &str) {
+    let (org_id, name) = match split_slug(slug) {
+        Some(parts) => parts,
+        None => {
+            eprintln!("Invalid slug {:?}, expected @org/name", slug);
+            exit(1);
+        }
+    };
+
+    let mut service = match fetch_catalogue(metadata_config, &org_id)
+        .await
+        .into_iter()
+        .find(|service| service.identifier == name)
+    {
+        Some(service) => service,
+        None => {
+            eprintln!(
+                "{} not found, run {} to find it",
+                slug,
+                "ginger-connector services search".blue()
+            );
+            exit(1);
+        }
+    };
+
+    println!("{}", service.slug().bold());
+    if let Some(description) = &service.description {
+        println!("{}", description);
+    }
+    println!("{} {}", "Type:".bold(), service.service_type());
+    if !service.owners.is_empty() {
+        println!("{} {}", "Owners:".bold(), service.owners.join(", "));
+    }
+    if let Some(repo) = &service.repo_origin {
+        println!("{} {}", "Repo:".bold(), repo);
+    }
+    if let Some(pipeline) = Pipeline::resolve(
+        service.repo_origin.as_deref(),
+        service.quick_links.as_deref(),
+    ) {
+        println!(
+            "{} {} on {}@{}",
+            "Pipeline:".bold(),
+            pipeline.config.provider,
+            pipeline.repo,
+            pipeline.config.default_branch()
+        );
+    }
+    print_quick_links(&service);
+    load_env_details(metadata_config, &mut service).await;
+    print_envs(&service);
+    summarize_spec(&service);
+}
@@ -0,0 +1,232 @@
+use std::{
+    fmt, fs,
+    path::{Component, Path, PathBuf},
+    process::exit,
+};
+
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::services_toml::config_section_or_exit;
+
+/// Key identifying the contract bundle published in place of an OpenAPI spec
+pub const CONTRACT_EXTENSION: &str = "x-ginger-contract";
+
+/// What a service publishes as its contract, configured in services.toml:
+///
+/// ```toml
+/// [contract]
+/// kind = "grpc"
+/// proto_files = ["proto/orders.proto", "proto/common/money.proto"]
+/// include_root = "proto" # the -I the imports are written against, the project root by default
+/// ```
+///
+/// or, for a GraphQL gateway:
+///
+/// ```toml
+/// [contract]
+/// kind = "graphql"
+/// schema_file = "schema.graphql"
+/// endpoint_path = "/graphql"
+/// ```
+///
+/// Services without a `[contract]` section publish an OpenAPI spec.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContractKind {
+    OpenApi,
+    Grpc,
+    GraphQL,
+}
+
+impl ContractKind {
+    fn parse(value: &str) -> Option<ContractKind> {
+        match value.to_lowercase().as_str() {
+            "openapi" => Some(ContractKind::OpenApi),
+            "grpc" | "protobuf" => Some(ContractKind::Grpc),
+            "graphql" => Some(ContractKind::GraphQL),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ContractKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            ContractKind::OpenApi => "openapi",
+            ContractKind::Grpc => "grpc",
+            ContractKind::GraphQL => "graphql",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+pub fn read_contract_kind(config_path: &Path) -> ContractKind {
+    let kind = match read_contract_value(config_path, "kind") {
+        Some(kind) => kind,
+        None => return ContractKind::OpenApi,
+    };
+    ContractKind::parse(&kind).unwrap_or_else(|| {
+        eprintln!(
+            "Unknown contract kind {:?} in services.toml, expected openapi, grpc or graphql",
+            kind
+        );
+        exit(1);
+    })
+}
+
+/// Kind of contract published by a dependency, read back from the published spec
+pub fn published_contract_kind(spec: &str) -> ContractKind {
+    serde_json::from_str::<JsonValue>(spec)
+        .ok()
+        .and_then(|spec| {
+            spec.get(CONTRACT_EXTENSION)
+                .and_then(|k| k.as_str())
+                .and_then(ContractKind::parse)
+        })
+        .unwrap_or(ContractKind::OpenApi)
+}
+
+/// Whether a published OpenAPI spec has anything to generate a client from. Services that
+/// only publish an AsyncAPI document, and portals publishing their usage, have neither an
+/// `openapi` version nor `paths`
+pub fn has_openapi_paths(spec: &str) -> bool {
+    match serde_json::from_str::<JsonValue>(spec) {
+        Ok(spec) => spec.get("openapi").is_some() && spec.get("paths").is_some(),
+        Err(_) => false,
+    }
+}
+
+fn read_contract_value(config_path: &Path, key: &str) -> Option<String> {
+    config_section_or_exit(config_path, "contract")
+        .and_then(|section| section.get(key).and_then(|v| v.as_str().map(String::from)))
+}
+
+fn read_file_list(config_path: &Path, key: &str) -> Vec<String> {
+    config_section_or_exit(config_path, "contract")
+        .and_then(|section| section.get(key).cloned())
+        .and_then(|files| files.as_array().cloned())
+        .map(|files| {
+            files
+                .iter()
+                .filter_map(|f| f.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn read_files(paths: &[String]) -> Map<String, JsonValue> {
+    let mut files = Map::new();
+    for path in paths {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                files.insert(path.clone(), JsonValue::String(content));
+            }
+            Err(e) => {
+                eprintln!(
+                    "Unable to read {}: {:?}. Aborting metadata publishing.",
+                    path, e
+                );
+                exit(1);
+            }
+        }
+    }
+    files
+}
+
+fn without_cur_dir(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+/// Reads the protos keyed by their path relative to the include root, so that consumers
+/// compiling the bundle with the root of its tree as include path resolve the same imports
+fn read_protos(paths: &[String], include_root: &str) -> Map<String, JsonValue> {
+    let include_root = without_cur_dir(Path::new(include_root));
+    read_files(paths)
+        .into_iter()
+        .map(|(path, content)| {
+            match without_cur_dir(Path::new(&path)).strip_prefix(&include_root) {
+                Ok(relative) => (relative.to_string_lossy().to_string(), content),
+                Err(_) => {
+                    eprintln!(
+                        "{} is not under the include_root {}. Aborting metadata publishing.",
+                        path,
+                        include_root.display()
+                    );
+                    exit(1);
+                }
+            }
+        })
+        .collect()
+}
+
+/// Bundles the contract files of a non OpenAPI service into the document published as its spec
+pub fn bundle_contract(config_path: &Path, kind: ContractKind) -> String {
+    match kind {
+        ContractKind::OpenApi => String::new(),
+        ContractKind::Grpc => {
+            let proto_files = read_file_list(config_path, "proto_files");
+            if proto_files.is_empty() {
+                eprintln!("No proto_files configured in the [contract] section of services.toml. Aborting metadata publishing.");
+                exit(1);
+            }
+            json!({
+                CONTRACT_EXTENSION: kind.to_string(),
+                "protos": read_protos(
+                    &proto_files,
+                    &read_contract_value(config_path, "include_root").unwrap_or_default()
+                ),
+            })
+            .to_string()
+        }
+        ContractKind::GraphQL => {
+            let schema_file = read_contract_value(config_path, "schema_file").unwrap_or_else(|| {
+                eprintln!("No schema_file configured in the [contract] section of services.toml. Aborting metadata publishing.");
+                exit(1);
+            });
+            let schema = fs::read_to_string(&schema_file).unwrap_or_else(|e| {
+                eprintln!(
+                    "Unable to read {}: {:?}. Aborting metadata publishing.",
+                    schema_file, e
+                );
+                exit(1);
+            });
+            json!({
+                CONTRACT_EXTENSION: kind.to_string(),
+                "schema": schema,
+                "endpoint_path": read_contract_value(config_path, "endpoint_path")
+                    .unwrap_or("/graphql".to_string()),
+            })
+            .to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contract_kinds_are_read_back_from_the_published_spec() {
+        assert_eq!(
+            published_contract_kind(r#"{"x-ginger-contract": "grpc", "protos": {}}"#),
+            ContractKind::Grpc
+        );
+        assert_eq!(
+            published_contract_kind(r#"{"openapi": "3.0.0", "paths": {}}"#),
+            ContractKind::OpenApi
+        );
+        assert_eq!(published_contract_kind(""), ContractKind::OpenApi);
+    }
+
+    #[test]
+    fn only_specs_with_paths_get_an_openapi_client() {
+        assert!(has_openapi_paths(r#"{"openapi": "3.0.0", "paths": {}}"#));
+        assert!(!has_openapi_paths(
+            r#"{"x-ginger-asyncapi": {"asyncapi": "2.6.0"}}"#
+        ));
+        assert!(!has_openapi_paths(r#"{"openapi": "3.0.0"}"#));
+        assert!(!has_openapi_paths(r#"{"x-ginger-usage": {}}"#));
+        assert!(!has_openapi_paths(""));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::{
+    environments::EnvKey,
+    graph::{DependencyGraph, Node},
+};
+
+/// How `--dry-run` prints the plan
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanOutput {
+    Text,
+    Json,
+}
+
+impl PlanOutput {
+    pub fn new(json: bool) -> PlanOutput {
+        if json {
+            PlanOutput::Json
+        } else {
+            PlanOutput::Text
+        }
+    }
+}
+
+/// A pipeline that would be dispatched, and why
+#[derive(Debug, Serialize)]
+pub struct PlannedPipeline {
+    pub slug: String,
+    pub kind: String,
+    /// `host/owner/repo` whose pipeline is dispatched
+    pub repo: Option<String>,
+    pub provider: Option<String>,
+    pub branch: Option<String>,
+    /// Starts at 1, a wave is only dispatched once the previous one passed
+    pub wave: usize,
+    /// Dependency path that leads to the pipeline, ending with it
+    pub path: Vec<String>,
+}
+
+impl PlannedPipeline {
+    pub fn new(node: &Node, env: &EnvKey, wave: usize, path: Vec<String>) -> PlannedPipeline {
+        PlannedPipeline {
+            slug: node.slug.clone(),
+            kind: node.kind.to_string(),
+            repo: node.pipeline.as_ref().map(|p| p.repo.to_string()),
+            provider: node
+                .pipeline
+                .as_ref()
+                .map(|p| p.config.provider.to_string()),
+            branch: node
+                .pipeline
+                .as_ref()
+                .map(|p| p.config.branch(env).to_string()),
+            wave,
+            path,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotDispatched {
+    pub slug: String,
+    pub reason: String,
+}
+
+/// What a pipeline triggering command would do, printed instead of dispatching with `--dry-run`
+#[derive(Debug, Default, Serialize)]
+pub struct DispatchPlan {
+    /// In dispatch order
+    pub pipelines: Vec<PlannedPipeline>,
+    pub not_dispatched: Vec<NotDispatched>,
+}
+
+impl DispatchPlan {
+    pub fn new() -> DispatchPlan {
+        DispatchPlan::default()
+    }
+
+    pub fn dispatch(&mut self, pipeline: PlannedPipeline) {
+        self.pipelines.push(pipeline);
+    }
+
+    pub fn skip(&mut self, slug: &str, reason: impl Into<String>) {
+        self.not_dispatched.push(NotDispatched {
+            slug: slug.to_string(),
+            reason: reason.into(),
+        });
+    }
+
+    pub fn print(&self, output: PlanOutput) {
+        if output == PlanOutput::Json {
+            println!("{}", serde_json::to_string_pretty(self).unwrap());
+            return;
+        }
+
+        println!(
+            "{}",
+            "Dry run, nothing is dispatched nor marked as waiting".yellow()
+        );
+        if self.pipelines.is_empty() {
+            println!("No pipelines to dispatch");
+        }
+        for (index, pipeline) in self.pipelines.iter().enumerate() {
+            let target = match (&pipeline.repo, &pipeline.provider, &pipeline.branch) {
+                (Some(repo), Some(provider), Some(branch)) => {
+                    format!("{} on {}@{}", provider, repo, branch)
+                }
+                _ => "no pipeline".to_string(),
+            };
+            println!(
+                "{:>3}. {} ({}, wave {}) {}",
+                index + 1,
+                pipeline.slug.bold(),
+                pipeline.kind,
+                pipeline.wave,
+                target
+            );
+            if pipeline.path.len() > 1 {
+                println!("     via {}", pipeline.path.join(" -> ").dimmed());
+            }
+        }
+        if !self.not_dispatched.is_empty() {
+            println!("{}", "Not dispatched:".bold());
+            for skipped in &self.not_dispatched {
+                println!("     {} : {}", skipped.slug, skipped.reason);
+            }
+        }
+    }
+}
+
+/// Dependency path from a pipeline of the first wave to `slug`, going back one wave at a time
+pub fn path_from_first_wave(
+    graph: &DependencyGraph,
+    waves: &[Vec<String>],
+    slug: &str,
+) -> Vec<String> {
+    let wave_of: BTreeMap<&str, usize> = waves
+        .iter()
+        .enumerate()
+        .flat_map(|(index, wave)| wave.iter().map(move |slug| (slug.as_str(), index)))
+        .collect();
+
+    let mut path = vec![slug.to_string()];
+    while let Some(&wave) = wave_of.get(path.last().unwrap().as_str()) {
+        if wave == 0 {
+            break;
+        }
+        match graph
+            .dependencies_of(path.last().unwrap())
+            .into_iter()
+            .find(|dep| wave_of.get(dep) == Some(&(wave - 1)))
+        {
+            Some(dep) => path.push(dep.to_string()),
+            None => break,
+        }
+    }
+    path.reverse();
+    path
+}
@@ -0,0 +1,399 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    path::Path,
+    str::FromStr,
+};
+
+use crate::services_toml::config_section_or_exit;
+
+const BUILT_IN_ENVS: [&str; 6] = ["dev", "stage", "prod", "prod_k8", "stage_k8", "preview"];
+
+/// Name of a deployment environment, e.g. `stage` or `preview-PR-123`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnvKey(String);
+
+impl EnvKey {
+    pub fn new(name: &str) -> EnvKey {
+        EnvKey(name.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_dev(&self) -> bool {
+        self.0 == "dev"
+    }
+
+    /// The temporary env a pull request is deployed to
+    pub fn preview(id: &str) -> EnvKey {
+        EnvKey(format!("preview-{}", id))
+    }
+
+    pub fn preview_id(&self) -> Option<&str> {
+        self.0.strip_prefix("preview-")
+    }
+
+    /// `preview` itself or one of its deployments, whose urls only exist once expanded
+    pub fn is_preview(&self) -> bool {
+        self.0 == "preview" || self.preview_id().is_some()
+    }
+
+    /// Preview urls in services.toml can contain `{preview_id}`,
+    /// e.g. `https://pr-{preview_id}.api-preview.gingersociety.org/iam`
+    pub fn expand_url(&self, url: &str) -> String {
+        match self.preview_id() {
+            Some(id) => url.replace("{preview_id}", id),
+            None => url.to_string(),
+        }
+    }
+
+    /// In cluster envs serve the same spec as their public counterpart, which is the one
+    /// reachable from where `publish` runs
+    pub fn spec_source(&self) -> EnvKey {
+        match self.0.strip_suffix("_k8") {
+            Some(public) => EnvKey::new(public),
+            None => self.clone(),
+        }
+    }
+}
+
+impl FromStr for EnvKey {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.is_empty() {
+            return Err("the environment name cannot be empty".to_string());
+        }
+        if !value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!(
+                "invalid environment name {:?}, only letters, digits, '-' and '_' are allowed",
+                value
+            ));
+        }
+        Ok(EnvKey(value.to_string()))
+    }
+}
+
+impl fmt::Display for EnvKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The environments available to this project. The built in ones are always available,
+/// others are declared in services.toml along with an optional fallback used when a service
+/// has no url for them:
+///
+/// ```toml
+/// [environments.qa]
+/// fallback = "stage"
+///
+/// [environments.perf]
+/// fallback = "prod"
+/// ```
+pub struct Environments {
+    declared: BTreeSet<String>,
+    fallbacks: HashMap<String, String>,
+}
+
+impl Environments {
+    pub fn load(config_path: &Path) -> Environments {
+        let mut declared: BTreeSet<String> =
+            BUILT_IN_ENVS.iter().map(|env| env.to_string()).collect();
+        let mut fallbacks: HashMap<String, String> = HashMap::from([
+            ("prod_k8".to_string(), "prod".to_string()),
+            ("stage_k8".to_string(), "stage".to_string()),
+            ("preview".to_string(), "stage".to_string()),
+        ]);
+
+        if let Some(section) = config_section_or_exit(config_path, "environments") {
+            if let Some(envs) = section.as_table() {
+                for (name, env_config) in envs {
+                    declared.insert(name.clone());
+                    if let Some(fallback) = env_config.get("fallback").and_then(|f| f.as_str()) {
+                        fallbacks.insert(name.clone(), fallback.to_string());
+                    }
+                }
+            }
+        }
+
+        Environments {
+            declared,
+            fallbacks,
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.declared.iter().cloned().collect()
+    }
+
+    /// Declared envs match exactly, `<declared>-<suffix>` matches too so that
+    /// `preview-PR-123` picks up the configuration of `preview`
+    fn declaration_of(&self, env: &EnvKey) -> Option<String> {
+        if self.declared.contains(env.as_str()) {
+            return Some(env.as_str().to_string());
+        }
+        self.declared
+            .iter()
+            .filter(|name| env.as_str().starts_with(&format!("{}-", name)))
+            .max_by_key(|name| name.len())
+            .cloned()
+    }
+
+    pub fn ensure_known(&self, env: &EnvKey) -> Result<(), String> {
+        match self.declaration_of(env) {
+            Some(_) => Ok(()),
+            None => Err(format!(
+                "Unknown environment {:?}. Available envs: {}. Declare new ones in the [environments] section of services.toml",
+                env.as_str(),
+                self.names().join(", ")
+            )),
+        }
+    }
+
+    /// The env followed by its fallbacks, in lookup order
+    pub fn chain(&self, env: &EnvKey) -> Vec<String> {
+        let mut chain = vec![env.as_str().to_string()];
+        let mut current = match self.declaration_of(env) {
+            Some(declared) if declared == env.as_str() => self.fallbacks.get(&declared).cloned(),
+            // `preview-PR-123` first tries its own key, then the one it was declared as
+            declared => declared,
+        };
+        while let Some(next) = current {
+            if chain.contains(&next) {
+                break;
+            }
+            current = self.fallbacks.get(&next).cloned();
+            chain.push(next);
+        }
+        chain
+    }
+
+    /// Url of a preview env (`preview-PR-123`), expanded. Only an explicit `preview` value
+    /// containing `{preview_id}` is used: following the fallbacks would register the url of
+    /// another env as the one of the preview
+    pub fn resolve_preview(
+        &self,
+        values: &HashMap<String, String>,
+        env: &EnvKey,
+    ) -> Result<String, String> {
+        let declaration = self
+            .declaration_of(env)
+            .unwrap_or_else(|| env.as_str().to_string());
+        match values.get(&declaration) {
+            Some(url) if url.contains("{preview_id}") => Ok(env.expand_url(url)),
+            Some(url) => Err(format!(
+                "The {} url {:?} has no {{preview_id}}, every preview would share it",
+                declaration, url
+            )),
+            None => Err(format!(
+                "No {} url, add one containing {{preview_id}} to deploy previews",
+                declaration
+            )),
+        }
+    }
+
+    /// Looks the env up in a per env map (`services`, `urls`, `urls_ws`, `portals_refs`),
+    /// following the fallback chain
+    pub fn resolve<'a>(
+        &self,
+        values: &'a HashMap<String, String>,
+        env: &EnvKey,
+    ) -> Result<&'a String, String> {
+        let chain = self.chain(env);
+        for name in &chain {
+            if let Some(value) = values.get(name) {
+                return Ok(value);
+            }
+        }
+        let mut available: Vec<&String> = values.keys().collect();
+        available.sort();
+        Err(format!(
+            "No value for env {:?} (tried {}). Available envs: {}",
+            env.as_str(),
+            chain.join(" -> "),
+            available
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    fn built_in() -> Environments {
+        Environments::load(Path::new("no/such/dir/services.toml"))
+    }
+
+    /// Loads a services.toml of its own for each test, they run in parallel
+    fn declared(test: &str, content: &str) -> Environments {
+        let dir = env::temp_dir().join(format!("environments_{}_{}", process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("services.toml");
+        fs::write(&config_path, content).unwrap();
+        let environments = Environments::load(&config_path);
+        fs::remove_dir_all(&dir).unwrap();
+        environments
+    }
+
+    fn urls(values: &[(&str, &str)]) -> HashMap<String, String> {
+        values
+            .iter()
+            .map(|(env, url)| (env.to_string(), url.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn built_in_envs_fall_back_to_their_public_counterpart() {
+        let environments = built_in();
+        assert_eq!(environments.chain(&EnvKey::new("dev")), vec!["dev"]);
+        assert_eq!(
+            environments.chain(&EnvKey::new("prod_k8")),
+            vec!["prod_k8", "prod"]
+        );
+        assert_eq!(
+            environments.chain(&EnvKey::preview("PR-12")),
+            vec!["preview-PR-12", "preview", "stage"]
+        );
+    }
+
+    #[test]
+    fn declared_envs_chain_their_fallbacks() {
+        let environments = declared(
+            "fallbacks",
+            r#"
+[environments.qa]
+fallback = "stage"
+
+[environments.perf]
+fallback = "prod_k8"
+
+[environments.sandbox]
+"#,
+        );
+        assert_eq!(environments.chain(&EnvKey::new("qa")), vec!["qa", "stage"]);
+        assert_eq!(
+            environments.chain(&EnvKey::new("perf")),
+            vec!["perf", "prod_k8", "prod"]
+        );
+        assert_eq!(environments.chain(&EnvKey::new("sandbox")), vec!["sandbox"]);
+        assert!(environments.ensure_known(&EnvKey::new("sandbox")).is_ok());
+        assert!(environments.ensure_known(&EnvKey::new("uat")).is_err());
+    }
+
+    #[test]
+    fn a_fallback_cycle_stops_at_the_first_repeated_env() {
+        let environments = declared(
+            "cycle",
+            r#"
+[environments.blue]
+fallback = "green"
+
+[environments.green]
+fallback = "blue"
+"#,
+        );
+        assert_eq!(
+            environments.chain(&EnvKey::new("blue")),
+            vec!["blue", "green"]
+        );
+        assert_eq!(
+            environments.resolve(&urls(&[("prod", "https://prod")]), &EnvKey::new("blue")),
+            Err(
+                "No value for env \"blue\" (tried blue -> green). Available envs: prod".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn suffixed_envs_use_the_longest_declaration() {
+        let environments = declared(
+            "suffixes",
+            r#"
+[environments.preview-eu]
+fallback = "prod"
+"#,
+        );
+        assert!(environments.ensure_known(&EnvKey::preview("PR-12")).is_ok());
+        assert_eq!(
+            environments.chain(&EnvKey::new("preview-eu-PR-12")),
+            vec!["preview-eu-PR-12", "preview-eu", "prod"]
+        );
+        assert!(environments.ensure_known(&EnvKey::new("stage-2")).is_ok());
+        assert!(environments.ensure_known(&EnvKey::new("staging")).is_err());
+    }
+
+    #[test]
+    fn values_are_resolved_along_the_chain() {
+        let environments = built_in();
+        let values = urls(&[("stage", "https://stage"), ("prod", "https://prod")]);
+        assert_eq!(
+            environments.resolve(&values, &EnvKey::new("prod_k8")),
+            Ok(&"https://prod".to_string())
+        );
+        assert_eq!(
+            environments.resolve(&values, &EnvKey::preview("PR-12")),
+            Ok(&"https://stage".to_string())
+        );
+        assert_eq!(
+            environments.resolve(&values, &EnvKey::new("dev")),
+            Err("No value for env \"dev\" (tried dev). Available envs: prod, stage".to_string())
+        );
+    }
+
+    #[test]
+    fn in_cluster_envs_read_the_spec_of_their_public_env() {
+        assert_eq!(EnvKey::new("stage_k8").spec_source(), EnvKey::new("stage"));
+        assert_eq!(EnvKey::new("prod_k8").spec_source(), EnvKey::new("prod"));
+        assert_eq!(EnvKey::new("prod").spec_source(), EnvKey::new("prod"));
+        assert_eq!(
+            EnvKey::preview("PR-12").spec_source(),
+            EnvKey::preview("PR-12")
+        );
+    }
+
+    #[test]
+    fn preview_urls_need_their_own_preview_id() {
+        let environments = built_in();
+        let env = EnvKey::preview("PR-12");
+        assert_eq!(env.preview_id(), Some("PR-12"));
+        assert_eq!(
+            environments.resolve_preview(
+                &urls(&[("preview", "https://pr-{preview_id}.preview/iam")]),
+                &env
+            ),
+            Ok("https://pr-PR-12.preview/iam".to_string())
+        );
+        assert!(environments
+            .resolve_preview(&urls(&[("preview", "https://preview/iam")]), &env)
+            .is_err());
+        // No fallback to stage, which would register the stage url for the preview
+        assert!(environments
+            .resolve_preview(&urls(&[("stage", "https://stage/iam")]), &env)
+            .is_err());
+        assert_eq!(
+            EnvKey::new("stage").expand_url("https://{preview_id}"),
+            "https://{preview_id}"
+        );
+    }
+
+    #[test]
+    fn env_names_are_validated() {
+        assert_eq!("preview-PR-12".parse(), Ok(EnvKey::preview("PR-12")));
+        assert_eq!("stage_k8".parse(), Ok(EnvKey::new("stage_k8")));
+        assert!("".parse::<EnvKey>().is_err());
+        assert!("qa env".parse::<EnvKey>().is_err());
+        assert!("qa/1".parse::<EnvKey>().is_err());
+    }
+}
@@ -29,3 +29,48 @@ pub fn replace_in_file(file_path: &str, from: &str, to: &str) -> io::Result<()>
 
     Ok(())
 }
+
+/// Adds `entry` to the project's .gitignore, creating the file if needed
+pub fn add_to_gitignore(entry: &str) -> io::Result<()> {
+    let gitignore_path = Path::new(".gitignore");
+
+    if gitignore_path.exists() {
+        let gitignore_content = fs::read_to_string(gitignore_path)?;
+        if gitignore_content.lines().any(|line| line.trim() == entry) {
+            println!("'{}' is already in .gitignore", entry);
+        } else {
+            let mut gitignore_file = OpenOptions::new().append(true).open(gitignore_path)?;
+            writeln!(gitignore_file, "\n{}", entry)?;
+            println!("Added '{}' to .gitignore", entry);
+        }
+    } else {
+        let mut gitignore_file = fs::File::create(gitignore_path)?;
+        writeln!(gitignore_file, "{}", entry)?;
+        println!("Created .gitignore and added '{}'", entry);
+    }
+    Ok(())
+}
+
+/// Writes `content` to `path`, creating the missing parent directories. Errors are reported
+/// and the generation goes on with the other files
+pub fn write_file(path: &Path, content: &str) {
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            eprintln!("Error creating {}: {:?}", parent.display(), e);
+            return;
+        }
+    }
+    match OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+    {
+        Ok(mut file) => {
+            if let Err(e) = file.write_all(content.as_bytes()) {
+                eprintln!("Error writing to {}: {:?}", path.display(), e);
+            }
+        }
+        Err(e) => eprintln!("Error creating {}: {:?}", path.display(), e),
+    }
+}
@@ -0,0 +1,549 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+};
+
+use MetadataService::apis::{
+    configuration::Configuration as MetadataConfiguration,
+    default_api::{
+        metadata_get_dbschemas_and_tables, metadata_get_services_and_envs,
+        metadata_get_user_packages, MetadataGetDbschemasAndTablesParams,
+        MetadataGetServicesAndEnvsParams, MetadataGetUserPackagesParams,
+    },
+};
+
+use crate::{
+    environments::EnvKey,
+    pagination::{collect_pages, PAGE_SIZE},
+    pipeline_provider::Pipeline,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Package,
+    Service,
+    DbSchema,
+    Cache,
+    MessageQueue,
+    Portal,
+}
+
+impl NodeKind {
+    /// `update_type` of the pipeline status updates sent to the metadata service
+    pub fn update_type(&self) -> &'static str {
+        match self {
+            NodeKind::Package => "package",
+            NodeKind::Service | NodeKind::Portal => "service",
+            NodeKind::DbSchema | NodeKind::Cache | NodeKind::MessageQueue => "db",
+        }
+    }
+}
+
+impl fmt::Display for NodeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            NodeKind::Package => "package",
+            NodeKind::Service => "service",
+            NodeKind::DbSchema => "db schema",
+            NodeKind::Cache => "cache",
+            NodeKind::MessageQueue => "queue",
+            NodeKind::Portal => "portal",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    /// `@org/identifier`
+    pub slug: String,
+    pub kind: NodeKind,
+    /// Where the pipeline runs, when the repo origin and CI provider are known
+    pub pipeline: Option<Pipeline>,
+    /// Slugs this node depends on
+    pub dependencies: Vec<String>,
+    /// Pipeline status last recorded in Metadata for the env, e.g. `passing` or `failing`
+    pub status: Option<String>,
+}
+
+/// Result of planning a set of nodes in dependency order
+#[derive(Debug, Default)]
+pub struct Layers {
+    /// Each layer only depends on the previous ones, nodes are sorted within a layer
+    pub layers: Vec<Vec<String>>,
+    /// Dependency cycles (strongly connected components), never part of `layers`
+    pub cycles: Vec<Vec<String>>,
+    /// Nodes that are not in a cycle but depend, directly or not, on one
+    pub blocked: Vec<String>,
+}
+
+/// Dependency graph of the packages, services, portals and DB schemas of an organization.
+/// Dependencies on slugs that are not part of the graph (third party packages ...) are ignored.
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    nodes: BTreeMap<String, Node>,
+}
+
+impl DependencyGraph {
+    pub fn new() -> DependencyGraph {
+        DependencyGraph::default()
+    }
+
+    pub fn add_node(&mut self, node: Node) {
+        self.nodes.insert(node.slug.clone(), node);
+    }
+
+    pub fn get(&self, slug: &str) -> Option<&Node> {
+        self.nodes.get(slug)
+    }
+
+    /// Sorted by slug
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    /// Dependencies of the node that are part of the graph
+    pub fn dependencies_of(&self, slug: &str) -> Vec<&str> {
+        let mut dependencies: Vec<&str> = self
+            .nodes
+            .get(slug)
+            .map(|node| {
+                node.dependencies
+                    .iter()
+                    .filter(|dep| self.nodes.contains_key(*dep))
+                    .map(|dep| dep.as_str())
+                    .collect()
+            })
+            .unwrap_or_default();
+        dependencies.sort();
+        dependencies.dedup();
+        dependencies
+    }
+
+    /// Nodes that depend directly on `slug`
+    pub fn dependents_of(&self, slug: &str) -> Vec<&str> {
+        self.nodes
+            .values()
+            .filter(|node| node.dependencies.iter().any(|dep| dep == slug))
+            .map(|node| node.slug.as_str())
+            .collect()
+    }
+
+    /// Nodes that depend on `slug`, directly or transitively, excluding `slug` itself
+    pub fn downstream_of(&self, slug: &str) -> BTreeSet<String> {
+        let mut visited = BTreeSet::new();
+        let mut stack = vec![slug.to_string()];
+        while let Some(current) = stack.pop() {
+            for dependent in self.dependents_of(&current) {
+                if visited.insert(dependent.to_string()) {
+                    stack.push(dependent.to_string());
+                }
+            }
+        }
+        visited.remove(slug);
+        visited
+    }
+
+    /// `slug` and the nodes reached from it in at most `depth` steps (unlimited when `None`),
+    /// following the dependencies when `upstream` is set, else the dependents
+    pub fn reachable_from(
+        &self,
+        slug: &str,
+        upstream: bool,
+        depth: Option<usize>,
+    ) -> BTreeSet<String> {
+        let mut visited = BTreeSet::from([slug.to_string()]);
+        let mut queue = VecDeque::from([(slug.to_string(), 0)]);
+        while let Some((current, distance)) = queue.pop_front() {
+            if depth.map_or(false, |depth| distance >= depth) {
+                continue;
+            }
+            let next = match upstream {
+                true => self.dependencies_of(&current),
+                false => self.dependents_of(&current),
+            };
+            for slug in next {
+                if visited.insert(slug.to_string()) {
+                    queue.push_back((slug.to_string(), distance + 1));
+                }
+            }
+        }
+        visited
+    }
+
+    /// Shortest chain of dependents leading from `from` to `to`, both included
+    pub fn path_between(&self, from: &str, to: &str) -> Option<Vec<String>> {
+        let mut previous: BTreeMap<String, String> = BTreeMap::new();
+        let mut queue = VecDeque::from([from.to_string()]);
+        while let Some(current) = queue.pop_front() {
+            if current == to {
+                let mut path = vec![current];
+                while let Some(prev) = previous.get(path.last().unwrap()) {
+                    path.push(prev.clone());
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let mut dependents = self.dependents_of(&current);
+            dependents.sort();
+            for dependent in dependents {
+                if dependent != from && !previous.contains_key(dependent) {
+                    previous.insert(dependent.to_string(), current.clone());
+                    queue.push_back(dependent.to_string());
+                }
+            }
+        }
+        None
+    }
+
+    /// Dependency cycles among `subset`, found with Tarjan's strongly connected components
+    pub fn cycles_in(&self, subset: &BTreeSet<String>) -> Vec<Vec<String>> {
+        struct Tarjan<'a> {
+            graph: &'a DependencyGraph,
+            subset: &'a BTreeSet<String>,
+            index: usize,
+            indices: BTreeMap<&'a str, usize>,
+            low_links: BTreeMap<&'a str, usize>,
+            stack: Vec<&'a str>,
+            on_stack: BTreeSet<&'a str>,
+            components: Vec<Vec<String>>,
+        }
+
+        impl<'a> Tarjan<'a> {
+            fn visit(&mut self, slug: &'a str) {
+                self.indices.insert(slug, self.index);
+                self.low_links.insert(slug, self.index);
+                self.index += 1;
+                self.stack.push(slug);
+                self.on_stack.insert(slug);
+
+                let graph = self.graph;
+                for dep in graph.dependencies_of(slug) {
+                    if !self.subset.contains(dep) {
+                        continue;
+                    }
+                    if !self.indices.contains_key(dep) {
+                        self.visit(dep);
+                        let low = self.low_links[slug].min(self.low_links[dep]);
+                        self.low_links.insert(slug, low);
+                    } else if self.on_stack.contains(dep) {
+                        let low = self.low_links[slug].min(self.indices[dep]);
+                        self.low_links.insert(slug, low);
+                    }
+                }
+
+                if self.low_links[slug] == self.indices[slug] {
+                    let mut component = vec![];
+                    while let Some(member) = self.stack.pop() {
+                        self.on_stack.remove(member);
+                        component.push(member.to_string());
+                        if member == slug {
+                            break;
+                        }
+                    }
+                    let self_loop = graph.dependencies_of(slug).contains(&slug);
+                    if component.len() > 1 || self_loop {
+                        component.reverse();
+                        self.components.push(component);
+                    }
+                }
+            }
+        }
+
+        let mut tarjan = Tarjan {
+            graph: self,
+            subset,
+            index: 0,
+            indices: BTreeMap::new(),
+            low_links: BTreeMap::new(),
+            stack: vec![],
+            on_stack: BTreeSet::new(),
+            components: vec![],
+        };
+        for slug in self.nodes.keys() {
+            if subset.contains(slug) && !tarjan.indices.contains_key(slug.as_str()) {
+                tarjan.visit(slug);
+            }
+        }
+        tarjan.components
+    }
+
+    /// Topological layers of `subset`, only the dependencies within `subset` are considered.
+    /// Layer 0 has no dependencies, layer n only depends on layers before it.
+    pub fn layers_of(&self, subset: &BTreeSet<String>) -> Layers {
+        let mut remaining: BTreeMap<&str, BTreeSet<&str>> = subset
+            .iter()
+            .filter(|slug| self.nodes.contains_key(*slug))
+            .map(|slug| {
+                let deps = self
+                    .dependencies_of(slug)
+                    .into_iter()
+                    .filter(|dep| subset.contains(*dep))
+                    .collect();
+                (slug.as_str(), deps)
+            })
+            .collect();
+
+        let mut layers = vec![];
+        loop {
+            let ready: Vec<&str> = remaining
+                .iter()
+                .filter(|(_, deps)| deps.is_empty())
+                .map(|(slug, _)| *slug)
+                .collect();
+            if ready.is_empty() {
+                break;
+            }
+            for slug in &ready {
+                remaining.remove(slug);
+            }
+            for deps in remaining.values_mut() {
+                for slug in &ready {
+                    deps.remove(slug);
+                }
+            }
+            layers.push(ready.iter().map(|slug| slug.to_string()).collect());
+        }
+
+        let cycles = self.cycles_in(subset);
+        let in_cycle: BTreeSet<&String> = cycles.iter().flatten().collect();
+        let blocked = remaining
+            .keys()
+            .map(|slug| slug.to_string())
+            .filter(|slug| !in_cycle.contains(slug))
+            .collect();
+
+        Layers {
+            layers,
+            cycles,
+            blocked,
+        }
+    }
+
+    pub fn layers(&self) -> Layers {
+        self.layers_of(&self.nodes.keys().cloned().collect())
+    }
+}
+
+/// Kind of a schema registered by `register_db`, from its `db_type`
+fn schema_kind(db_type: &str) -> NodeKind {
+    let db_type = db_type.to_lowercase();
+    if db_type.contains("cache") {
+        NodeKind::Cache
+    } else if db_type.contains("queue") {
+        NodeKind::MessageQueue
+    } else {
+        NodeKind::DbSchema
+    }
+}
+
+/// Builds the graph of the organization in `env` from the metadata service. DB schemas, caches
+/// and message queues are dependencies of the services using them, so that their repos are
+/// the entry points of the org wide checks.
+pub async fn load_dependency_graph(
+    metadata_config: &MetadataConfiguration,
+    org_id: &str,
+    env: &EnvKey,
+) -> Result<DependencyGraph, String> {
+    let mut graph = DependencyGraph::new();
+
+    let packages = metadata_get_user_packages(
+        metadata_config,
+        MetadataGetUserPackagesParams {
+            org_id: org_id.to_string(),
+            env: env.to_string(),
+        },
+    )
+    .await
+    .map_err(|e| format!("Unable to get the packages of {}: {:?}", org_id, e))?;
+
+    for pkg in packages {
+        graph.add_node(Node {
+            slug: format!("@{}/{}", org_id, pkg.identifier),
+            kind: NodeKind::Package,
+            pipeline: Pipeline::resolve(
+                pkg.repo_origin.clone().flatten().as_deref(),
+                pkg.quick_links.clone().flatten().as_deref(),
+            ),
+            dependencies: pkg.dependencies.clone(),
+            status: pkg.pipeline_status.clone().flatten(),
+        });
+    }
+
+    let schemas = metadata_get_dbschemas_and_tables(
+        metadata_config,
+        MetadataGetDbschemasAndTablesParams {
+            org_id: org_id.to_string(),
+            env: env.to_string(),
+        },
+    )
+    .await
+    .map_err(|e| format!("Unable to get the DB schemas of {}: {:?}", org_id, e))?;
+
+    for schema in &schemas {
+        graph.add_node(Node {
+            slug: format!("@{}/{}", org_id, schema.name),
+            kind: schema_kind(&schema.db_type),
+            pipeline: Pipeline::resolve(
+                Some(&schema.repo_origin),
+                schema.quick_links.clone().flatten().as_deref(),
+            ),
+            dependencies: vec![],
+            status: schema.pipeline_status.clone().flatten(),
+        });
+    }
+
+    let services = collect_pages(|page| {
+        metadata_get_services_and_envs(
+            metadata_config,
+            MetadataGetServicesAndEnvsParams {
+                page_number: Some(page.to_string()),
+                page_size: Some(PAGE_SIZE.to_string()),
+                org_id: org_id.to_string(),
+            },
+        )
+    })
+    .await
+    .map_err(|e| format!("Unable to get the services of {}: {:?}", org_id, e))?;
+
+    for service in services {
+        let mut dependencies = service.dependencies.clone();
+        for schema in &schemas {
+            if schema.identifier == service.db_schema_id
+                || schema.identifier == service.message_queue_schema_id
+                || schema.identifier == service.cache_schema_id
+            {
+                dependencies.push(format!("@{}/{}", org_id, schema.name));
+            }
+        }
+
+        let kind = match service.service_type.clone().flatten().as_deref() {
+            Some("Portal") => NodeKind::Portal,
+            _ => NodeKind::Service,
+        };
+
+        graph.add_node(Node {
+            slug: format!("@{}/{}", org_id, service.identifier),
+            kind,
+            pipeline: Pipeline::resolve(
+                service.repo_origin.clone().flatten().as_deref(),
+                service.quick_links.clone().flatten().as_deref(),
+            ),
+            dependencies,
+            // Services are published per env, their status is on the entry of the env
+            status: service
+                .envs
+                .iter()
+                .find(|entry| entry.env_key == env.as_str())
+                .and_then(|entry| entry.pipeline_status.clone().flatten()),
+        });
+    }
+
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> DependencyGraph {
+        let mut graph = DependencyGraph::new();
+        for (slug, dependencies) in edges {
+            graph.add_node(Node {
+                slug: slug.to_string(),
+                kind: NodeKind::Service,
+                pipeline: None,
+                dependencies: dependencies.iter().map(|dep| dep.to_string()).collect(),
+                status: None,
+            });
+        }
+        graph
+    }
+
+    fn slugs(slugs: &[&str]) -> Vec<String> {
+        slugs.iter().map(|slug| slug.to_string()).collect()
+    }
+
+    #[test]
+    fn layers_follow_the_dependency_order() {
+        let graph = graph(&[
+            ("a", &[]),
+            ("b", &["a"]),
+            ("c", &["a", "b"]),
+            ("d", &[]),
+            ("e", &["d", "@third/party"]),
+        ]);
+        let layers = graph.layers();
+        assert_eq!(
+            layers.layers,
+            vec![slugs(&["a", "d"]), slugs(&["b", "e"]), slugs(&["c"])]
+        );
+        assert!(layers.cycles.is_empty());
+        assert!(layers.blocked.is_empty());
+    }
+
+    #[test]
+    fn cycles_are_reported_and_block_their_dependents() {
+        let graph = graph(&[
+            ("a", &["c"]),
+            ("b", &["a"]),
+            ("c", &["b"]),
+            ("d", &["a"]),
+            ("e", &["d"]),
+            ("f", &[]),
+        ]);
+        let layers = graph.layers();
+        assert_eq!(layers.layers, vec![slugs(&["f"])]);
+        assert_eq!(layers.cycles.len(), 1);
+        let mut cycle = layers.cycles[0].clone();
+        cycle.sort();
+        assert_eq!(cycle, slugs(&["a", "b", "c"]));
+        assert_eq!(layers.blocked, slugs(&["d", "e"]));
+    }
+
+    #[test]
+    fn self_dependencies_are_cycles() {
+        let graph = graph(&[("a", &["a"]), ("b", &["a"])]);
+        assert_eq!(graph.layers().cycles, vec![slugs(&["a"])]);
+        assert_eq!(graph.layers().blocked, slugs(&["b"]));
+    }
+
+    #[test]
+    fn separate_cycles_are_separate_components() {
+        let graph = graph(&[("a", &["b"]), ("b", &["a"]), ("c", &["d"]), ("d", &["c"])]);
+        let mut cycles: Vec<Vec<String>> = graph
+            .cycles_in(&graph.nodes().map(|node| node.slug.clone()).collect())
+            .into_iter()
+            .map(|mut cycle| {
+                cycle.sort();
+                cycle
+            })
+            .collect();
+        cycles.sort();
+        assert_eq!(cycles, vec![slugs(&["a", "b"]), slugs(&["c", "d"])]);
+    }
+
+    #[test]
+    fn only_the_subset_is_considered() {
+        let graph = graph(&[("a", &["b"]), ("b", &["a"]), ("c", &["a"])]);
+        let subset: BTreeSet<String> = slugs(&["a", "c"]).into_iter().collect();
+        assert!(graph.cycles_in(&subset).is_empty());
+        assert_eq!(
+            graph.layers_of(&subset).layers,
+            vec![slugs(&["a"]), slugs(&["c"])]
+        );
+    }
+
+    #[test]
+    fn reachable_from_stops_at_the_depth() {
+        let graph = graph(&[("a", &[]), ("b", &["a"]), ("c", &["b"])]);
+        assert_eq!(
+            graph.reachable_from("a", false, Some(1)),
+            BTreeSet::from(["a", "b"].map(String::from))
+        );
+        assert_eq!(
+            graph.reachable_from("c", true, None),
+            BTreeSet::from(["a", "b", "c"].map(String::from))
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use std::{collections::BTreeSet, path::Path, process::exit};
+
+use clap::ValueEnum;
+use colored::Colorize;
+use serde::Serialize;
+use MetadataService::apis::configuration::Configuration as MetadataConfiguration;
+
+use crate::{
+    environments::{EnvKey, Environments},
+    graph::{load_dependency_graph, DependencyGraph, Node, NodeKind},
+    services_toml::read_services_config,
+};
+
+/// Output of `graph`
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz, render with `dot -Tsvg`
+    Dot,
+    /// Mermaid flowchart, for markdown docs
+    Mermaid,
+    Json,
+}
+
+/// Which part of the graph is exported
+pub struct GraphSelection {
+    /// Only this node and its dependencies
+    pub upstream_of: Option<String>,
+    /// Only this node and its dependents, combined with `upstream_of` only the nodes in between
+    pub downstream_of: Option<String>,
+    /// How many dependency levels are followed from `upstream_of` / `downstream_of`
+    pub depth: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedNode<'a> {
+    slug: &'a str,
+    kind: String,
+    status: Option<&'a str>,
+    repo: Option<String>,
+}
+
+/// A dependency edge, pointing from the dependency to its dependent like the pipelines run
+#[derive(Debug, Serialize)]
+struct ExportedEdge<'a> {
+    from: &'a str,
+    to: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedGraph<'a> {
+    env: String,
+    nodes: Vec<ExportedNode<'a>>,
+    edges: Vec<ExportedEdge<'a>>,
+}
+
+fn kind_color(kind: NodeKind) -> &'static str {
+    match kind {
+        NodeKind::Package => "#dbeafe",
+        NodeKind::Service => "#dcfce7",
+        NodeKind::Portal => "#fae8ff",
+        NodeKind::DbSchema => "#fef3c7",
+        NodeKind::Cache => "#ffedd5",
+        NodeKind::MessageQueue => "#e0e7ff",
+    }
+}
+
+/// Border color of a node, from its pipeline status
+fn status_color(status: Option<&str>) -> &'static str {
+    match status {
+        Some("passing") => "#16a34a",
+        Some("failing") => "#dc2626",
+        Some("running") => "#2563eb",
+        Some("waiting") => "#d97706",
+        _ => "#9ca3af",
+    }
+}
+
+fn name_of(slug: &str) -> &str {
+    slug.rsplit('/').next().unwrap_or(slug)
+}
+
+/// `@org/name` from a slug or a bare name
+fn to_slug(org_id: &str, value: &str) -> String {
+    match value.starts_with('@') {
+        true => value.to_string(),
+        false => format!("@{}/{}", org_id, value),
+    }
+}
+
+/// Every node, or the ones reached from `upstream_of` / `downstream_of`. Given both, only the
+/// nodes reached from each, i.e. the ones between `downstream_of` and `upstream_of`
+fn select(graph: &DependencyGraph, org_id: &str, selection: &GraphSelection) -> BTreeSet<String> {
+    let roots = [
+        (selection.upstream_of.as_ref(), true),
+        (selection.downstream_of.as_ref(), false),
+    ];
+
+    let mut selected: Option<BTreeSet<String>> = None;
+    for (root, upstream) in roots {
+        let root = match root {
+            Some(root) => to_slug(org_id, root),
+            None => continue,
+        };
+        if graph.get(&root).is_none() {
+            eprintln!("{} is not part of the dependency graph", root);
+            exit(1);
+        }
+        let reached = graph.reachable_from(&root, upstream, selection.depth);
+        selected = Some(match selected {
+            Some(selected) => selected.intersection(&reached).cloned().collect(),
+            None => reached,
+        });
+    }
+    selected.unwrap_or_else(|| graph.nodes().map(|node| node.slug.clone()).collect())
+}
+
+/// Edges between the selected nodes, sorted
+fn edges_of<'a>(graph: &'a DependencyGraph, nodes: &[&'a Node]) -> Vec<ExportedEdge<'a>> {
+    let selected: BTreeSet<&str> = nodes.iter().map(|node| node.slug.as_str()).collect();
+    let mut edges = vec![];
+    for node in nodes.iter().copied() {
+        for dependency in graph.dependencies_of(&node.slug) {
+            if selected.contains(dependency) {
+                edges.push(ExportedEdge {
+                    from: dependency,
+                    to: &node.slug,
+                });
+            }
+        }
+    }
+    edges.sort_by(|a, b| (a.from, a.to).cmp(&(b.from, b.to)));
+    edges
+}
+
+fn label_of(node: &Node) -> String {
+    format!(
+        "{}\\n{} · {}",
+        name_of(&node.slug),
+        node.kind,
+        node.status.as_deref().unwrap_or("unknown")
+    )
+}
+
+fn to_dot(nodes: &[&Node], edges: &[ExportedEdge]) -> String {
+    let mut out = String::from("digraph dependencies {\n");
+    out.push_str("    rankdir=LR;\n");
+    out.push_str("    node [shape=box, style=\"rounded,filled\", fontname=\"Helvetica\"];\n");
+    for node in nodes {
+        out.push_str(&format!(
+            "    \"{}\" [label=\"{}\", fillcolor=\"{}\", color=\"{}\", penwidth=2];\n",
+            node.slug,
+            label_of(node),
+            kind_color(node.kind),
+            status_color(node.status.as_deref())
+        ));
+    }
+    for edge in edges {
+        out.push_str(&format!("    \"{}\" -> \"{}\";\n", edge.from, edge.to));
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn to_mermaid(nodes: &[&Node], edges: &[ExportedEdge]) -> String {
+    // Slugs are not valid Mermaid ids, nodes are numbered in slug order instead
+    let id_of = |slug: &str| {
+        nodes
+            .iter()
+            .position(|node| node.slug == slug)
+            .map(|index| format!("n{}", index))
+            .unwrap()
+    };
+
+    let mut out = String::from("flowchart LR\n");
+    for node in nodes {
+        out.push_str(&format!(
+            "    {}[\"{}\"]\n",
+            id_of(&node.slug),
+            label_of(node).replace("\\n", "<br/>")
+        ));
+    }
+    for edge in edges {
+        out.push_str(&format!(
+            "    {} --> {}\n",
+            id_of(edge.from),
+            id_of(edge.to)
+        ));
+    }
+    for node in nodes {
+        out.push_str(&format!(
+            "    style {} fill:{},stroke:{},stroke-width:2px\n",
+            id_of(&node.slug),
+            kind_color(node.kind),
+            status_color(node.status.as_deref())
+        ));
+    }
+    out
+}
+
+/// Prints the dependency graph of the org in `env`, the same one the pipelines are triggered
+/// along, colored by kind and by pipeline status
+pub async fn export_graph(
+    config_path: &Path,
+    metadata_config: &MetadataConfiguration,
+    env: &EnvKey,
+    format: GraphFormat,
+    selection: &GraphSelection,
+) {
+    let config = match read_services_config(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{:?}", e);
+            println!(
+                "There is no service configuration found. Please use {} to add one. Exiting",
+                "ginger-connector init".blue()
+            );
+            exit(1);
+        }
+    };
+
+    let environments = Environments::load(config_path);
+    if let Err(e) = environments.ensure_known(env) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+
+    let graph = match load_dependency_graph(metadata_config, &config.organization_id, env).await {
+        Ok(graph) => graph,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+
+    let selected = select(&graph, &config.organization_id, selection);
+    let nodes: Vec<&Node> = graph
+        .nodes()
+        .filter(|node| selected.contains(&node.slug))
+        .collect();
+    let edges = edges_of(&graph, &nodes);
+
+    let output = match format {
+        GraphFormat::Dot => to_dot(&nodes, &edges),
+        GraphFormat::Mermaid => to_mermaid(&nodes, &edges),
+        GraphFormat::Json => {
+            let exported = ExportedGraph {
+                env: env.to_string(),
+                nodes: nodes
+                    .iter()
+                    .copied()
+                    .map(|node| ExportedNode {
+                        slug: &node.slug,
+                        kind: node.kind.to_string(),
+                        status: node.status.as_deref(),
+                        repo: node.pipeline.as_ref().map(|p| p.repo.to_string()),
+                    })
+                    .collect(),
+                edges,
+            };
+            serde_json::to_string_pretty(&exported).unwrap()
+        }
+    };
+    println!("{}", output.trim_end());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORG: &str = "gingersociety";
+
+    fn node(name: &str, kind: NodeKind, status: Option<&str>, dependencies: &[&str]) -> Node {
+        Node {
+            slug: to_slug(ORG, name),
+            kind,
+            pipeline: None,
+            dependencies: dependencies.iter().map(|dep| to_slug(ORG, dep)).collect(),
+            status: status.map(String::from),
+        }
+    }
+
+    /// Postgres <- IAMService <- AdminPortal, and Postgres <- Orders
+    fn graph() -> DependencyGraph {
+        let mut graph = DependencyGraph::new();
+        graph.add_node(node("Postgres", NodeKind::DbSchema, Some("passing"), &[]));
+        graph.add_node(node(
+            "IAMService",
+            NodeKind::Service,
+            Some("failing"),
+            &["Postgres"],
+        ));
+        graph.add_node(node(
+            "AdminPortal",
+            NodeKind::Portal,
+            None,
+            &["IAMService", "@third/party"],
+        ));
+        graph.add_node(node(
+            "Orders",
+            NodeKind::Service,
+            Some("running"),
+            &["Postgres"],
+        ));
+        graph
+    }
+
+    fn selection(
+        upstream_of: Option<&str>,
+        downstream_of: Option<&str>,
+        depth: Option<usize>,
+    ) -> GraphSelection {
+        GraphSelection {
+            upstream_of: upstream_of.map(String::from),
+            downstream_of: downstream_of.map(String::from),
+            depth,
+        }
+    }
+
+    fn selected_names(selection: &GraphSelection) -> Vec<String> {
+        select(&graph(), ORG, selection)
+            .iter()
+            .map(|slug| name_of(slug).to_string())
+            .collect()
+    }
+
+    fn selected_nodes<'a>(graph: &'a DependencyGraph, selection: &GraphSelection) -> Vec<&'a Node> {
+        let selected = select(graph, ORG, selection);
+        graph
+            .nodes()
+            .filter(|node| selected.contains(&node.slug))
+            .collect()
+    }
+
+    #[test]
+    fn everything_is_selected_without_roots() {
+        assert_eq!(
+            selected_names(&selection(None, None, None)),
+            vec!["AdminPortal", "IAMService", "Orders", "Postgres"]
+        );
+    }
+
+    #[test]
+    fn roots_select_their_dependencies_or_dependents() {
+        assert_eq!(
+            selected_names(&selection(Some("AdminPortal"), None, None)),
+            vec!["AdminPortal", "IAMService", "Postgres"]
+        );
+        assert_eq!(
+            selected_names(&selection(None, Some("@gingersociety/IAMService"), None)),
+            vec!["AdminPortal", "IAMService"]
+        );
+        assert_eq!(
+            selected_names(&selection(None, Some("Postgres"), None)),
+            vec!["AdminPortal", "IAMService", "Orders", "Postgres"]
+        );
+    }
+
+    #[test]
+    fn depth_limits_the_levels_followed() {
+        assert_eq!(
+            selected_names(&selection(Some("AdminPortal"), None, Some(1))),
+            vec!["AdminPortal", "IAMService"]
+        );
+        assert_eq!(
+            selected_names(&selection(None, Some("Postgres"), Some(1))),
+            vec!["IAMService", "Orders", "Postgres"]
+        );
+        assert_eq!(
+            selected_names(&selection(None, Some("Postgres"), Some(0))),
+            vec!["Postgres"]
+        );
+    }
+
+    #[test]
+    fn both_roots_select_the_nodes_between_them() {
+        assert_eq!(
+            selected_names(&selection(Some("AdminPortal"), Some("Postgres"), None)),
+            vec!["AdminPortal", "IAMService", "Postgres"]
+        );
+        assert_eq!(
+            selected_names(&selection(Some("Orders"), Some("IAMService"), None)),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn dot_output_colors_nodes_by_kind_and_status() {
+        let graph = graph();
+        let nodes = selected_nodes(&graph, &selection(Some("IAMService"), None, None));
+        let edges = edges_of(&graph, &nodes);
+        assert_eq!(
+            to_dot(&nodes, &edges),
+            r##"digraph dependencies {
+    rankdir=LR;
+    node [shape=box, style="rounded,filled", fontname="Helvetica"];
+    "@gingersociety/IAMService" [label="IAMService\nservice · failing", fillcolor="#dcfce7", color="#dc2626", penwidth=2];
+    "@gingersociety/Postgres" [label="Postgres\ndb schema · passing", fillcolor="#fef3c7", color="#16a34a", penwidth=2];
+    "@gingersociety/Postgres" -> "@gingersociety/IAMService";
+}
+"##
+        );
+    }
+
+    #[test]
+    fn mermaid_output_numbers_nodes_in_slug_order() {
+        let graph = graph();
+        let nodes = selected_nodes(&graph, &selection(Some("AdminPortal"), None, Some(1)));
+        let edges = edges_of(&graph, &nodes);
+        assert_eq!(
+            to_mermaid(&nodes, &edges),
+            r##"flowchart LR
+    n0["AdminPortal<br/>portal · unknown"]
+    n1["IAMService<br/>service · failing"]
+    n1 --> n0
+    style n0 fill:#fae8ff,stroke:#9ca3af,stroke-width:2px
+    style n1 fill:#dcfce7,stroke:#dc2626,stroke-width:2px
+"##
+        );
+    }
+}
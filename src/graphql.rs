@@ -0,0 +1,275 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use ginger_shared_rs::LANG;
+use serde_json::Value as JsonValue;
+
+use crate::{base_urls::BaseUrls, file_utils::write_file};
+
+fn generate_ts_client(
+    output_dir: &Path,
+    service_name: &str,
+    endpoint_path: &str,
+    base_urls: &BaseUrls,
+) {
+    write_file(
+        &output_dir.join("codegen.yml"),
+        &format!(
+            r#"schema: {dir}/schema.graphql
+generates:
+  {dir}/types.ts:
+    plugins:
+      - typescript
+"#,
+            dir = output_dir.display()
+        ),
+    );
+
+    match Command::new("npx")
+        .arg("graphql-codegen")
+        .arg("--config")
+        .arg(output_dir.join("codegen.yml"))
+        .output()
+    {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            eprintln!("graphql-codegen failed:");
+            for line in String::from_utf8_lossy(&output.stderr).split('\n') {
+                eprintln!("{}", line)
+            }
+            println!(
+                "Typed GraphQL clients need {}",
+                "pnpm add -D @graphql-codegen/cli @graphql-codegen/typescript"
+            );
+        }
+        Err(e) => eprintln!("Failed to execute graphql-codegen: {:?}", e),
+    }
+
+    write_file(
+        &output_dir.join("index.ts"),
+        &format!(
+            r#"/* tslint:disable */
+/* eslint-disable */
+
+export * from './types';
+
+{base_urls}
+export const GRAPHQL_PATH = '{endpoint_path}';
+
+const getToken = (): string | null => {{
+    return localStorage.getItem('access_token'); // Adjust the key name as needed
+}};
+
+export interface GraphQLResponse<TData> {{
+    data?: TData;
+    errors?: Array<{{ message: string; path?: Array<string | number> }}>;
+}}
+
+export const request = async <TData, TVariables = Record<string, unknown>>(
+    query: string,
+    variables?: TVariables
+): Promise<GraphQLResponse<TData>> => {{
+    const token = getToken();
+    const response = await fetch(getBaseUrl() + GRAPHQL_PATH, {{
+        method: 'POST',
+        headers: {{
+            'Content-Type': 'application/json',
+            ...(token ? {{ Authorization: token }} : {{}}),
+        }},
+        body: JSON.stringify({{ query, variables }}),
+    }});
+    return response.json();
+}};
+
+export default request;
+"#,
+            base_urls = base_urls.render_ts(service_name),
+            endpoint_path = endpoint_path
+        ),
+    );
+}
+
+/// Named operations of the `.graphql` documents kept in `<client>/queries`, as (document path
+/// relative to the client, operation name)
+fn collect_operations(output_dir: &Path) -> Vec<(String, String)> {
+    let mut documents: Vec<PathBuf> = match fs::read_dir(output_dir.join("queries")) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "graphql"))
+            .collect(),
+        Err(_) => return vec![],
+    };
+    documents.sort();
+
+    let mut operations = vec![];
+    for document in documents {
+        let content = match fs::read_to_string(&document) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error reading {}: {:?}", document.display(), e);
+                continue;
+            }
+        };
+        let file_name = document.file_name().unwrap().to_string_lossy();
+        for line in content.lines() {
+            let line = line.trim_start();
+            let name = ["query", "mutation", "subscription"]
+                .iter()
+                .find_map(|keyword| line.strip_prefix(keyword))
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .map(|rest| {
+                    rest.trim_start()
+                        .chars()
+                        .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                        .collect::<String>()
+                });
+            match name {
+                Some(name) if !name.is_empty() => {
+                    operations.push((format!("queries/{}", file_name), name))
+                }
+                Some(_) => println!(
+                    "Skipping an anonymous operation in {}, name it to generate its types",
+                    document.display()
+                ),
+                None => {}
+            }
+        }
+    }
+    operations
+}
+
+fn generate_rust_crate(
+    output_dir: &Path,
+    crate_name: &str,
+    endpoint_path: &str,
+    base_urls: &BaseUrls,
+) {
+    write_file(
+        &output_dir.join("Cargo.toml"),
+        &format!(
+            r#"[package]
+name = "{crate_name}"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+graphql_client = "0.14"
+reqwest = {{ version = "0.12", features = ["json"] }}
+serde = {{ version = "1", features = ["derive"] }}
+"#,
+            crate_name = crate_name
+        ),
+    );
+
+    // graphql_client resolves both paths from the manifest of this crate
+    let operations = collect_operations(output_dir);
+    let derives: String = operations
+        .iter()
+        .map(|(query_path, name)| {
+            format!(
+                r#"#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.graphql",
+    query_path = "{query_path}",
+    response_derives = "Debug, Clone"
+)]
+pub struct {name};
+
+"#,
+                query_path = query_path,
+                name = name
+            )
+        })
+        .collect();
+
+    write_file(
+        &output_dir.join("src/lib.rs"),
+        &format!(
+            r#"//! Typed operations of the `.graphql` documents in `queries/`, checked against the
+//! published `schema.graphql`. Each operation gets a struct and a module with its
+//! `Variables` and `ResponseData`, run them with `post::<Operation>(..)`.
+
+pub use graphql_client::{{GraphQLQuery, Response}};
+
+{derives}{base_urls}
+pub const GRAPHQL_PATH: &str = "{endpoint_path}";
+
+pub async fn post<Q: GraphQLQuery>(
+    variables: Q::Variables,
+    token: Option<&str>,
+) -> Result<Response<Q::ResponseData>, reqwest::Error> {{
+    let mut request = reqwest::Client::new()
+        .post(format!("{{}}{{}}", base_url(), GRAPHQL_PATH))
+        .json(&Q::build_query(variables));
+    if let Some(token) = token {{
+        request = request.header("Authorization", token);
+    }}
+    request.send().await?.json().await
+}}
+"#,
+            derives = derives,
+            base_urls = base_urls.render_rust(crate_name),
+            endpoint_path = endpoint_path
+        ),
+    );
+
+    if operations.is_empty() {
+        println!(
+            "No operations found, add them as .graphql documents in {} and run connect again",
+            output_dir.join("queries").display()
+        );
+    }
+    println!(
+        "please add \n\n{} = {{ path = \"{}\" }}\n\n in cargo.toml file if not added",
+        crate_name,
+        output_dir.display()
+    );
+}
+
+/// Writes the published SDL under `<dir>/<Service>_client` and generates a typed client
+/// resolving its base url at runtime like the OpenAPI clients
+pub fn generate_graphql_client(
+    bundle: &JsonValue,
+    service_name: &str,
+    lang: LANG,
+    root_dir: &str,
+    base_urls: &BaseUrls,
+) {
+    let output_dir = PathBuf::from(format!("{}/{}_client", root_dir, service_name));
+    println!("Generating GraphQL client for: {}", service_name);
+
+    let schema = match bundle.get("schema").and_then(|s| s.as_str()) {
+        Some(schema) => schema,
+        None => {
+            eprintln!("The published contract of {} has no schema", service_name);
+            return;
+        }
+    };
+    let endpoint_path = bundle
+        .get("endpoint_path")
+        .and_then(|p| p.as_str())
+        .unwrap_or("/graphql");
+
+    write_file(&output_dir.join("schema.graphql"), schema);
+
+    match lang {
+        LANG::TS => generate_ts_client(&output_dir, service_name, endpoint_path, base_urls),
+        LANG::Rust => generate_rust_crate(&output_dir, service_name, endpoint_path, base_urls),
+        LANG::Python | LANG::Shell => {
+            println!(
+                "Typed GraphQL clients are not supported for {}, the schema is available in {}",
+                lang,
+                output_dir.display()
+            );
+            return;
+        }
+    }
+
+    println!(
+        "GraphQL client generated successfully in directory: {}",
+        output_dir.display()
+    );
+}
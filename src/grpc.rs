@@ -0,0 +1,277 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+use ginger_shared_rs::LANG;
+use serde_json::Value as JsonValue;
+
+use crate::{base_urls::BaseUrls, file_utils::write_file};
+
+/// Keeps the published layout of the protos, relative to the include root of the service so
+/// that their imports resolve against `proto/`, while refusing paths escaping the output directory
+fn sanitize_proto_path(path: &str) -> PathBuf {
+    Path::new(path)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Protobuf packages declared in the protos, used to include the tonic generated modules.
+/// Protos without a package are generated by prost as `_`
+fn proto_packages(protos: &[(PathBuf, String)]) -> Vec<String> {
+    let mut packages: Vec<String> = protos
+        .iter()
+        .map(|(_, content)| {
+            content
+                .lines()
+                .find_map(|line| {
+                    line.trim()
+                        .strip_prefix("package ")
+                        .map(|package| package.trim_end_matches(';').trim().to_string())
+                })
+                .unwrap_or_else(|| "_".to_string())
+        })
+        .collect();
+    packages.sort();
+    packages.dedup();
+    packages
+}
+
+fn run_generator(command: &mut Command, tool: &str) -> bool {
+    match command.output() {
+        Ok(output) if output.status.success() => true,
+        Ok(output) => {
+            eprintln!("{} failed:", tool);
+            for line in String::from_utf8_lossy(&output.stderr).split('\n') {
+                eprintln!("{}", line)
+            }
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to execute {}: {:?}", tool, e);
+            false
+        }
+    }
+}
+
+fn generate_rust_crate(
+    output_dir: &Path,
+    crate_name: &str,
+    protos: &[(PathBuf, String)],
+    base_urls: &BaseUrls,
+) {
+    write_file(
+        &output_dir.join("Cargo.toml"),
+        &format!(
+            r#"[package]
+name = "{crate_name}"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+prost = "0.12"
+tonic = "0.11"
+
+[build-dependencies]
+tonic-build = "0.11"
+"#,
+            crate_name = crate_name
+        ),
+    );
+
+    let proto_list: String = protos
+        .iter()
+        .map(|(path, _)| format!("\"proto/{}\"", path.display()))
+        .collect::<Vec<String>>()
+        .join(", ");
+    write_file(
+        &output_dir.join("build.rs"),
+        &format!(
+            r#"fn main() -> Result<(), Box<dyn std::error::Error>> {{
+    tonic_build::configure()
+        .build_server(false)
+        .compile(&[{proto_list}], &["proto"])?;
+    Ok(())
+}}
+"#,
+            proto_list = proto_list
+        ),
+    );
+
+    let modules: String = proto_packages(protos)
+        .iter()
+        .map(|package| match package.as_str() {
+            "_" => "tonic::include_proto!(\"_\");\n\n".to_string(),
+            _ => format!(
+                "pub mod {module} {{\n    tonic::include_proto!(\"{package}\");\n}}\n\n",
+                module = package.replace('.', "_"),
+                package = package
+            ),
+        })
+        .collect();
+    write_file(
+        &output_dir.join("src/lib.rs"),
+        &format!(
+            r#"{modules}{base_urls}
+pub async fn get_channel() -> Result<tonic::transport::Channel, tonic::transport::Error> {{
+    tonic::transport::Endpoint::from_shared(base_url())?
+        .connect()
+        .await
+}}
+"#,
+            modules = modules,
+            base_urls = base_urls.render_rust(crate_name)
+        ),
+    );
+
+    println!(
+        "please add \n\n{} = {{ path = \"{}\" }}\n\n in cargo.toml file if not added",
+        crate_name,
+        output_dir.display()
+    );
+}
+
+fn generate_ts_stubs(
+    output_dir: &Path,
+    service_name: &str,
+    protos: &[(PathBuf, String)],
+    base_urls: &BaseUrls,
+) -> bool {
+    let mut command = Command::new("protoc");
+    command
+        .arg("--plugin=./node_modules/.bin/protoc-gen-ts_proto")
+        .arg(format!("--ts_proto_out={}", output_dir.display()))
+        .arg("--ts_proto_opt=outputServices=grpc-js,esModuleInterop=true")
+        .arg(format!("-I{}", output_dir.join("proto").display()));
+    for (path, _) in protos {
+        command.arg(output_dir.join("proto").join(path));
+    }
+    if !run_generator(&mut command, "protoc (ts-proto)") {
+        println!(
+            "ts-proto stubs need {} and {}",
+            "protoc", "pnpm add -D ts-proto && pnpm add @grpc/grpc-js"
+        );
+        return false;
+    }
+
+    write_file(
+        &output_dir.join("config.ts"),
+        &format!(
+            r#"/* tslint:disable */
+/* eslint-disable */
+
+import {{ credentials }} from '@grpc/grpc-js'
+
+{base_urls}
+export const getCredentials = () =>
+  getBaseUrl().startsWith('https://') ? credentials.createSsl() : credentials.createInsecure()
+
+export const getAddress = () => getBaseUrl().replace(/^https?:\/\//, '')
+"#,
+            base_urls = base_urls.render_ts(service_name)
+        ),
+    );
+    true
+}
+
+fn generate_python_stubs(
+    output_dir: &Path,
+    service_name: &str,
+    protos: &[(PathBuf, String)],
+    base_urls: &BaseUrls,
+) -> bool {
+    let mut command = Command::new("python");
+    command
+        .arg("-m")
+        .arg("grpc_tools.protoc")
+        .arg(format!("-I{}", output_dir.join("proto").display()))
+        .arg(format!("--python_out={}", output_dir.display()))
+        .arg(format!("--grpc_python_out={}", output_dir.display()));
+    for (path, _) in protos {
+        command.arg(output_dir.join("proto").join(path));
+    }
+    if !run_generator(&mut command, "grpc_tools.protoc") {
+        println!("grpcio stubs need {}", "pip install grpcio-tools");
+        return false;
+    }
+
+    write_file(
+        &output_dir.join("config_utils.py"),
+        &format!(
+            r#"import grpc
+{base_urls}
+
+def get_channel():
+    base_url = get_base_url()
+    address = base_url.split("://", 1)[-1]
+    if base_url.startswith("https://"):
+        return grpc.secure_channel(address, grpc.ssl_channel_credentials())
+    return grpc.insecure_channel(address)
+"#,
+            base_urls = base_urls.render_python(service_name)
+        ),
+    );
+    write_file(&output_dir.join("__init__.py"), "");
+    true
+}
+
+/// Writes the published protos under `<dir>/<Service>_client/proto` and generates the
+/// stubs for the project language, resolving their base url at runtime like the OpenAPI clients
+pub fn generate_grpc_client(
+    bundle: &JsonValue,
+    service_name: &str,
+    lang: LANG,
+    root_dir: &str,
+    base_urls: &BaseUrls,
+) {
+    let output_dir = PathBuf::from(format!("{}/{}_client", root_dir, service_name));
+    println!("Generating gRPC client for: {}", service_name);
+
+    let protos: Vec<(PathBuf, String)> = bundle
+        .get("protos")
+        .and_then(|p| p.as_object())
+        .map(|protos| {
+            protos
+                .iter()
+                .filter_map(|(path, content)| {
+                    content
+                        .as_str()
+                        .map(|content| (sanitize_proto_path(path), content.to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if protos.is_empty() {
+        eprintln!("The published contract of {} has no protos", service_name);
+        return;
+    }
+
+    for (path, content) in &protos {
+        write_file(&output_dir.join("proto").join(path), content);
+    }
+
+    let generated = match lang {
+        LANG::Rust => {
+            generate_rust_crate(&output_dir, service_name, &protos, base_urls);
+            true
+        }
+        LANG::TS => generate_ts_stubs(&output_dir, service_name, &protos, base_urls),
+        LANG::Python => generate_python_stubs(&output_dir, service_name, &protos, base_urls),
+        LANG::Shell => {
+            println!("gRPC stubs are not supported for {}", lang);
+            false
+        }
+    };
+
+    if generated {
+        println!(
+            "gRPC client generated successfully in directory: {}",
+            output_dir.display()
+        );
+    }
+}
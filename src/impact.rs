@@ -0,0 +1,412 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    process::exit,
+};
+
+use colored::Colorize;
+use reqwest::Client;
+use serde_json::Value as JsonValue;
+use MetadataService::apis::{
+    configuration::Configuration as MetadataConfiguration,
+    default_api::{
+        metadata_get_service_and_env_by_id, metadata_get_services_and_envs,
+        MetadataGetServiceAndEnvByIdParams, MetadataGetServicesAndEnvsParams,
+    },
+};
+
+use crate::{
+    environments::{EnvKey, Environments},
+    pagination::{collect_pages, PAGE_SIZE},
+    publish::read_package_info,
+    services_toml::read_services_config,
+    spec_diff::{diff_specs, SpecChange},
+};
+
+/// Extension key under which a consumer publishes, in its own spec, the operationIds it
+/// calls on each of its dependencies (`{"@org/Service": ["operationId", ...]}`)
+pub const USAGE_EXTENSION: &str = "x-ginger-usage";
+
+struct Consumer {
+    slug: String,
+    service_type: String,
+    /// operationIds of the changed service this consumer calls, if it published them
+    usage: Option<HashSet<String>>,
+}
+
+async fn read_local_spec(
+    spec_path: &Option<String>,
+    urls: &Option<HashMap<String, String>>,
+    spec_url: &Option<String>,
+    environments: &Environments,
+    env: &EnvKey,
+) -> String {
+    if let Some(spec_path) = spec_path {
+        return fs::read_to_string(spec_path).unwrap_or_else(|e| {
+            eprintln!("Unable to read the spec from {}: {:?}", spec_path, e);
+            exit(1);
+        });
+    }
+
+    let base_url = match urls
+        .as_ref()
+        .map(|urls| environments.resolve(urls, &env.spec_source()))
+    {
+        Some(Ok(url)) => url.clone(),
+        Some(Err(e)) => {
+            eprintln!("{}. Pass the spec path explicitly", e);
+            exit(1);
+        }
+        None => {
+            eprintln!("No urls found in services.toml, pass the spec path explicitly");
+            exit(1);
+        }
+    };
+    let full_url = format!(
+        "{}{}",
+        base_url,
+        spec_url.clone().unwrap_or("/openapi.json".to_string())
+    );
+    println!("Fetching the local spec from {}", full_url);
+
+    match Client::new().get(&full_url).send().await {
+        Ok(response) if response.status().is_success() => response.text().await.unwrap(),
+        Ok(response) => {
+            eprintln!("Failed to fetch the spec: {}", response.status());
+            exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error making the GET request: {:?}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Reads the operationIds a consumer declared it calls on `dependency` from its published spec
+pub fn read_published_usage(spec: &str, dependency: &str) -> Option<HashSet<String>> {
+    let spec: JsonValue = serde_json::from_str(spec).ok()?;
+    let operations = spec.get(USAGE_EXTENSION)?.get(dependency)?.as_array()?;
+    Some(
+        operations
+            .iter()
+            .filter_map(|op| op.as_str().map(|s| s.to_string()))
+            .collect(),
+    )
+}
+
+/// operationIds of the broken operations, and whether some of them have none, in which case
+/// they can't be matched against the usage sets
+fn affected_operations(changes: &[SpecChange]) -> (HashSet<String>, bool) {
+    let operations = changes
+        .iter()
+        .filter_map(|change| change.operation_id.clone())
+        .collect();
+    let unmatched = changes.iter().any(|change| change.operation_id.is_none());
+    (operations, unmatched)
+}
+
+#[derive(Debug, PartialEq)]
+enum Impact {
+    /// The sorted operationIds the consumer calls that are broken, which fails the report
+    Broken(Vec<String>),
+    PossiblyAffected(&'static str),
+    NotAffected,
+}
+
+fn impact_on(
+    usage: &Option<HashSet<String>>,
+    affected_operations: &HashSet<String>,
+    unmatched_changes: bool,
+) -> Impact {
+    let usage = match usage {
+        Some(usage) => usage,
+        None => {
+            return Impact::PossiblyAffected("no usage information published, possibly affected")
+        }
+    };
+    let mut affected: Vec<String> = usage.intersection(affected_operations).cloned().collect();
+    affected.sort();
+    if !affected.is_empty() {
+        Impact::Broken(affected)
+    } else if unmatched_changes {
+        Impact::PossiblyAffected("operations without operationId changed, possibly affected")
+    } else {
+        Impact::NotAffected
+    }
+}
+
+pub async fn impact_report(
+    config_path: &Path,
+    env: EnvKey,
+    metadata_config: &MetadataConfiguration,
+    spec_path: &Option<String>,
+) {
+    let services_config = match read_services_config(config_path) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{:?}", e);
+            println!(
+                "There is no service configuration found or the existing one is invalid. Please use {} to add one. Exiting",
+                "ginger-connector init".blue()
+            );
+            exit(1);
+        }
+    };
+
+    let package_info = read_package_info(&services_config.lang).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    let mut name = package_info.name;
+    let organization = package_info.organization;
+
+    if let Some(override_name) = services_config.override_name.clone() {
+        name = override_name
+    }
+
+    let environments = Environments::load(config_path);
+    if let Err(e) = environments.ensure_known(&env) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+
+    let local_spec = read_local_spec(
+        spec_path,
+        &services_config.urls,
+        &services_config.spec_url,
+        &environments,
+        &env,
+    )
+    .await;
+
+    let published_spec = match metadata_get_service_and_env_by_id(
+        metadata_config,
+        MetadataGetServiceAndEnvByIdParams {
+            service_identifier: name.clone(),
+            env: env.to_string(),
+            org_id: organization.clone(),
+        },
+    )
+    .await
+    {
+        Ok(response) => response.spec,
+        Err(e) => {
+            println!("{:?}", e);
+            println!(
+                "{} is not published in {} yet, nothing to compare",
+                name, env
+            );
+            exit(1);
+        }
+    };
+
+    let changes: Vec<SpecChange> = match (
+        serde_json::from_str::<JsonValue>(&published_spec),
+        serde_json::from_str::<JsonValue>(&local_spec),
+    ) {
+        (Ok(old_spec), Ok(new_spec)) => diff_specs(&old_spec, &new_spec),
+        _ => {
+            eprintln!("Unable to parse the specs as JSON");
+            exit(1);
+        }
+    };
+
+    if changes.is_empty() {
+        println!(
+            "No breaking changes in the local spec of {} compared to {}",
+            name, env
+        );
+        return;
+    }
+
+    println!("Breaking changes in {} compared to {}:", name, env);
+    for change in &changes {
+        println!("  {}", change.to_string().yellow());
+    }
+
+    let (affected_operations, unmatched_changes) = affected_operations(&changes);
+
+    let slug = format!("@{}/{}", organization, name);
+
+    let services = match collect_pages(|page| {
+        metadata_get_services_and_envs(
+            metadata_config,
+            MetadataGetServicesAndEnvsParams {
+                page_number: Some(page.to_string()),
+                page_size: Some(PAGE_SIZE.to_string()),
+                org_id: organization.clone(),
+            },
+        )
+    })
+    .await
+    {
+        Ok(services) => services,
+        Err(e) => {
+            println!("{:?}", e);
+            println!("Unable to get the services of {}", organization);
+            exit(1);
+        }
+    };
+
+    let mut consumers = vec![];
+    for service in services {
+        if !service.dependencies.contains(&slug) {
+            continue;
+        }
+
+        let usage = match metadata_get_service_and_env_by_id(
+            metadata_config,
+            MetadataGetServiceAndEnvByIdParams {
+                service_identifier: service.identifier.clone(),
+                env: env.to_string(),
+                org_id: service.organization_id.clone(),
+            },
+        )
+        .await
+        {
+            Ok(response) => read_published_usage(&response.spec, &slug),
+            Err(_) => None,
+        };
+
+        consumers.push(Consumer {
+            slug: format!("@{}/{}", service.organization_id, service.identifier),
+            service_type: service
+                .service_type
+                .clone()
+                .flatten()
+                .unwrap_or("Unknown".to_string()),
+            usage,
+        });
+    }
+
+    if consumers.is_empty() {
+        println!("No services or portals depend on {}", slug);
+        return;
+    }
+
+    println!("\nConsumers of {}:", slug);
+    let mut broken = 0;
+    for consumer in &consumers {
+        let verdict = match impact_on(&consumer.usage, &affected_operations, unmatched_changes) {
+            Impact::Broken(affected) => {
+                broken += 1;
+                format!("{} {:?}", "calls affected operations".red(), affected)
+            }
+            Impact::PossiblyAffected(reason) => reason.yellow().to_string(),
+            Impact::NotAffected => "not affected".green().to_string(),
+        };
+        println!(
+            "  {} ({}) : {}",
+            consumer.slug, consumer.service_type, verdict
+        );
+    }
+
+    if broken > 0 {
+        eprintln!(
+            "{} consumer(s) call operations broken by these changes",
+            broken
+        );
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::usage::{attach_usage, UsageSet};
+
+    const SLUG: &str = "@gingersociety/IAMService";
+
+    fn users_api() -> JsonValue {
+        json!({
+            "openapi": "3.0.0",
+            "paths": {
+                "/users": {
+                    "get": { "operationId": "listUsers", "responses": { "200": { "description": "Ok" } } }
+                },
+                "/users/{id}": {
+                    "get": { "operationId": "getUser", "responses": { "200": { "description": "Ok" } } },
+                    "delete": { "responses": { "204": { "description": "Deleted" } } }
+                }
+            }
+        })
+    }
+
+    fn usage(operations: &[&str]) -> Option<HashSet<String>> {
+        Some(operations.iter().map(|op| op.to_string()).collect())
+    }
+
+    fn without(path: &str, method: &str) -> JsonValue {
+        let mut spec = users_api();
+        spec["paths"][path].as_object_mut().unwrap().remove(method);
+        spec
+    }
+
+    #[test]
+    fn consumers_calling_a_broken_operation_fail_the_report() {
+        let changes = diff_specs(&users_api(), &without("/users/{id}", "get"));
+        let (affected, unmatched) = affected_operations(&changes);
+        assert!(!unmatched);
+        assert_eq!(
+            impact_on(&usage(&["listUsers", "getUser"]), &affected, unmatched),
+            Impact::Broken(vec!["getUser".to_string()])
+        );
+        assert_eq!(
+            impact_on(&usage(&["listUsers"]), &affected, unmatched),
+            Impact::NotAffected
+        );
+    }
+
+    #[test]
+    fn a_removed_path_breaks_each_of_its_operations() {
+        let mut new = users_api();
+        new["paths"].as_object_mut().unwrap().remove("/users/{id}");
+        let (affected, unmatched) = affected_operations(&diff_specs(&users_api(), &new));
+        // The removed `delete` has no operationId
+        assert!(unmatched);
+        assert_eq!(
+            impact_on(&usage(&["getUser"]), &affected, unmatched),
+            Impact::Broken(vec!["getUser".to_string()])
+        );
+        assert!(matches!(
+            impact_on(&usage(&["listUsers"]), &affected, unmatched),
+            Impact::PossiblyAffected(_)
+        ));
+    }
+
+    #[test]
+    fn consumers_without_usage_are_possibly_affected() {
+        let changes = diff_specs(&users_api(), &without("/users", "get"));
+        let (affected, unmatched) = affected_operations(&changes);
+        assert!(matches!(
+            impact_on(&None, &affected, unmatched),
+            Impact::PossiblyAffected(_)
+        ));
+        // Published by a consumer that doesn't list this service
+        assert!(matches!(
+            impact_on(
+                &read_published_usage(r#"{"x-ginger-usage": {}}"#, SLUG),
+                &affected,
+                unmatched
+            ),
+            Impact::PossiblyAffected(_)
+        ));
+    }
+
+    #[test]
+    fn usage_published_by_a_portal_without_spec_is_read() {
+        let usage_set: UsageSet = [(SLUG.to_string(), ["listUsers".to_string()].into())].into();
+        let published = read_published_usage(&attach_usage("", &usage_set), SLUG);
+        assert_eq!(published, usage(&["listUsers"]));
+
+        let (affected, unmatched) =
+            affected_operations(&diff_specs(&users_api(), &without("/users", "get")));
+        assert_eq!(
+            impact_on(&published, &affected, unmatched),
+            Impact::Broken(vec!["listUsers".to_string()])
+        );
+    }
+}
@@ -3,6 +3,8 @@ use std::{collections::HashMap, path::Path, process::exit};
 use ginger_shared_rs::{write_service_config_file, ServiceConfig, LANG};
 use inquire::{InquireError, Select, Text};
 
+use crate::{file_utils::add_to_gitignore, services_toml::LOCAL_OVERLAY_FILE};
+
 pub fn initialize(config_path: &Path) {
     let options = LANG::all();
 
@@ -32,7 +34,12 @@ pub fn initialize(config_path: &Path) {
                         portal_config: None,
                     };
                     match write_service_config_file(config_path, &config) {
-                        Ok(_) => println!("Success!"),
+                        Ok(_) => {
+                            println!("Success!");
+                            if let Err(e) = add_to_gitignore(LOCAL_OVERLAY_FILE) {
+                                println!("Failed to update .gitignore: {:?}", e);
+                            }
+                        }
                         Err(_) => println!("Unable to create the configuration. Please check if you have permission to create {:?}" , dir)
                     };
                 }
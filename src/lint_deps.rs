@@ -0,0 +1,347 @@
+use std::{fs, path::Path, process::exit};
+
+use colored::Colorize;
+use ginger_shared_rs::LANG;
+
+use crate::{
+    publish::read_package_info,
+    services_toml::read_services_config,
+    usage::{imports_client, source_files},
+};
+
+/// The source without its comments, so that commented out imports don't count. String
+/// literals are skipped so that `"http://..."` is not taken for a comment
+fn strip_comments(content: &str, lang: &LANG) -> String {
+    let (line_comment, block_comments, quotes): (&str, bool, &[char]) = match lang {
+        // Single quotes are lifetimes or chars in Rust
+        LANG::Rust => ("//", true, &['"']),
+        LANG::TS => ("//", true, &['"', '\'', '`']),
+        LANG::Python | LANG::Shell => ("#", false, &['"', '\'']),
+    };
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+    let mut quote: Option<char> = None;
+    while let Some(c) = rest.chars().next() {
+        let skipped = match quote {
+            Some(_) if c == '\\' => rest.chars().take(2).map(char::len_utf8).sum(),
+            Some(open) => {
+                if c == open {
+                    quote = None;
+                }
+                c.len_utf8()
+            }
+            None if rest.starts_with(line_comment) => {
+                rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+                continue;
+            }
+            None if block_comments && rest.starts_with("/*") => {
+                rest = rest[2..].find("*/").map_or("", |end| &rest[end + 4..]);
+                continue;
+            }
+            None if matches!(lang, LANG::Rust) && rest.starts_with("'\"'") => 3,
+            None => {
+                if quotes.contains(&c) {
+                    quote = Some(c);
+                }
+                c.len_utf8()
+            }
+        };
+        output.push_str(&rest[..skipped]);
+        rest = &rest[skipped..];
+    }
+    output
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// What precedes and follows each occurrence of the identifier `name`, ignoring the ones
+/// that are part of a longer identifier (`ginger_shared` in `ginger_shared_rs`)
+fn occurrences<'a>(content: &'a str, name: &str) -> Vec<(&'a str, &'a str)> {
+    content
+        .match_indices(name)
+        .filter_map(|(index, _)| {
+            let before = &content[..index];
+            let after = &content[index + name.len()..];
+            let bounded = !matches!(before.chars().next_back(), Some(c) if is_ident_char(c))
+                && !matches!(after.chars().next(), Some(c) if is_ident_char(c));
+            bounded.then_some((before, after))
+        })
+        .collect()
+}
+
+/// Whether the source imports the internal package `package_name`, aliased or not. Comments
+/// are expected to be stripped already
+fn imports_package(content: &str, lang: &LANG, org_id: &str, package_name: &str) -> bool {
+    let follows = |before: &str, keywords: &[&str]| {
+        let before = before.trim_end();
+        keywords.iter().any(|keyword| {
+            matches!(before.strip_suffix(keyword), Some(rest)
+                if !matches!(rest.chars().next_back(), Some(c) if is_ident_char(c)))
+        })
+    };
+    match lang {
+        LANG::Rust => {
+            let crate_name = package_name.replace('-', "_");
+            occurrences(content, &crate_name)
+                .into_iter()
+                .any(|(before, after)| {
+                    after.starts_with("::") || follows(before, &["use", "crate"])
+                })
+        }
+        LANG::Python => {
+            let module_name = package_name.replace('-', "_");
+            occurrences(content, &module_name)
+                .into_iter()
+                .any(|(before, _)| follows(before, &["import", "from"]))
+        }
+        LANG::TS | LANG::Shell => {
+            let import_path = format!("@{}/{}", org_id, package_name);
+            ['\'', '"'].iter().any(|quote| {
+                [*quote, '/']
+                    .iter()
+                    .any(|end| content.contains(&format!("{}{}{}", quote, import_path, end)))
+            })
+        }
+    }
+}
+
+/// Flags the service dependencies whose generated client is never imported and the internal
+/// packages that are declared but never used. Every stale edge published as a dependency
+/// triggers this project's pipeline needlessly, so this exits non zero when any are found.
+pub fn lint_dependencies(config_path: &Path) {
+    let services_config = match read_services_config(config_path) {
+        Ok(c) => c,
+        Err(err) => {
+            println!("{:?}", err);
+            println!(
+                "There is no service configuration found. Please use {} to add one. Exiting",
+                "ginger-connector init".blue()
+            );
+            exit(1);
+        }
+    };
+
+    let package_info = read_package_info(&services_config.lang).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+
+    let files: Vec<String> = source_files(&services_config.lang, &services_config.dir)
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .map(|content| strip_comments(&content, &services_config.lang))
+        .collect();
+
+    let mut unused_services = vec![];
+    if let Some(services) = &services_config.services {
+        for slug in services.keys() {
+            let service_name = slug.split('/').last().unwrap_or(slug);
+            if !files
+                .iter()
+                .any(|content| imports_client(content, &services_config.lang, service_name))
+            {
+                unused_services.push(slug.clone());
+            }
+        }
+    }
+
+    let mut unused_packages = vec![];
+    for dependency in package_info.dependencies {
+        let (org_id, package_name) = match dependency.trim_start_matches('@').split_once('/') {
+            Some((org_id, package_name)) => (org_id.to_string(), package_name.to_string()),
+            None => continue,
+        };
+        if !files
+            .iter()
+            .any(|content| imports_package(content, &services_config.lang, &org_id, &package_name))
+        {
+            unused_packages.push(dependency.clone());
+        }
+    }
+
+    unused_services.sort();
+    unused_packages.sort();
+
+    for slug in &unused_services {
+        println!(
+            "{} service {} is declared in services.toml but its client is never imported",
+            "unused".yellow(),
+            slug
+        );
+    }
+    for dependency in &unused_packages {
+        println!(
+            "{} internal package {} is declared but never imported",
+            "unused".yellow(),
+            dependency
+        );
+    }
+
+    if unused_services.is_empty() && unused_packages.is_empty() {
+        println!("{}", "All declared dependencies are in use".green());
+    } else {
+        println!(
+            "Remove unused services with {} and drop the unused packages from the manifest",
+            "ginger-connector config".blue()
+        );
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn imports(source: &str, lang: &LANG, package_name: &str) -> bool {
+        imports_package(
+            &strip_comments(source, lang),
+            lang,
+            "gingersociety",
+            package_name,
+        )
+    }
+
+    #[test]
+    fn rust_imports_are_found_aliased_or_not() {
+        let lang = LANG::Rust;
+        assert!(imports(
+            "use ginger_shared_rs::LANG;",
+            &lang,
+            "ginger-shared-rs"
+        ));
+        assert!(imports(
+            "use ginger_shared_rs as shared;",
+            &lang,
+            "ginger-shared-rs"
+        ));
+        assert!(imports(
+            "pub use ginger_shared_rs;",
+            &lang,
+            "ginger-shared-rs"
+        ));
+        assert!(imports(
+            "extern crate ginger_shared_rs;",
+            &lang,
+            "ginger-shared-rs"
+        ));
+        assert!(imports(
+            "fn main() { ginger_shared_rs::run(); }",
+            &lang,
+            "ginger-shared-rs"
+        ));
+        assert!(!imports(
+            "use ginger_shared_rs_extra::X;",
+            &lang,
+            "ginger-shared-rs"
+        ));
+        assert!(!imports(
+            "let ginger_shared_rs = 1;",
+            &lang,
+            "ginger-shared-rs"
+        ));
+    }
+
+    #[test]
+    fn commented_out_rust_imports_are_ignored() {
+        let lang = LANG::Rust;
+        assert!(!imports(
+            "// use ginger_shared_rs::LANG;",
+            &lang,
+            "ginger-shared-rs"
+        ));
+        assert!(!imports(
+            "/* use ginger_shared_rs::LANG;\n   ginger_shared_rs::run(); */",
+            &lang,
+            "ginger-shared-rs"
+        ));
+        assert!(!imports(
+            "/// See `ginger_shared_rs::LANG`\nfn lang() {}",
+            &lang,
+            "ginger-shared-rs"
+        ));
+        // Neither a url nor a char literal start a comment
+        assert!(imports(
+            "let url = \"http://localhost\"; use ginger_shared_rs::LANG;",
+            &lang,
+            "ginger-shared-rs"
+        ));
+        assert!(imports(
+            "fn quote<'a>(s: &'a str) -> char { '\"' }\nuse ginger_shared_rs::LANG;",
+            &lang,
+            "ginger-shared-rs"
+        ));
+    }
+
+    #[test]
+    fn python_imports_are_found_aliased_or_not() {
+        let lang = LANG::Python;
+        assert!(imports("import ginger_utils as gu", &lang, "ginger-utils"));
+        assert!(imports(
+            "from ginger_utils.auth import login",
+            &lang,
+            "ginger-utils"
+        ));
+        assert!(imports("import ginger_utils.auth", &lang, "ginger-utils"));
+        assert!(!imports("# import ginger_utils", &lang, "ginger-utils"));
+        assert!(!imports("import ginger_utils_extra", &lang, "ginger-utils"));
+        // A `#` in a string doesn't start a comment
+        assert!(imports(
+            "url = \"http://localhost/#top\"; import ginger_utils",
+            &lang,
+            "ginger-utils"
+        ));
+    }
+
+    #[test]
+    fn ts_imports_are_found_aliased_or_not() {
+        let lang = LANG::TS;
+        assert!(imports(
+            "import { Button as UiButton } from '@gingersociety/ui';",
+            &lang,
+            "ui"
+        ));
+        assert!(imports(
+            "import * as ui from \"@gingersociety/ui/button\";",
+            &lang,
+            "ui"
+        ));
+        assert!(imports(
+            "const ui = require('@gingersociety/ui');",
+            &lang,
+            "ui"
+        ));
+        assert!(!imports(
+            "import kit from '@gingersociety/ui-kit';",
+            &lang,
+            "ui"
+        ));
+        assert!(!imports(
+            "// import ui from '@gingersociety/ui';",
+            &lang,
+            "ui"
+        ));
+        assert!(!imports(
+            "/*\nimport ui from '@gingersociety/ui';\n*/",
+            &lang,
+            "ui"
+        ));
+        assert!(imports(
+            "const docs = 'https://example.com'; import ui from '@gingersociety/ui';",
+            &lang,
+            "ui"
+        ));
+    }
+
+    #[test]
+    fn commented_out_client_imports_are_ignored() {
+        let source = "// use IAMService::apis::default_api;\nfn main() {}";
+        assert!(imports_client(source, &LANG::Rust, "IAMService"));
+        assert!(!imports_client(
+            &strip_comments(source, &LANG::Rust),
+            &LANG::Rust,
+            "IAMService"
+        ));
+    }
+}
@@ -2,19 +2,29 @@ use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use catalogue::{describe_service, search_services};
+use clap::{Args, Parser, Subcommand};
+use dispatch_plan::PlanOutput;
+use environments::EnvKey;
 use generate::generate_arbitrary_client;
 use ginger_shared_rs::utils::{get_token_from_file_storage, split_slug};
-use ginger_shared_rs::{Environment, LANG};
+use ginger_shared_rs::LANG;
+use graph_export::{export_graph, GraphFormat, GraphSelection};
+use impact::impact_report;
 use init::initialize;
+use lint_deps::lint_dependencies;
+use orchestrator::{orchestrate_system_check, retry_failed, OrchestrateOptions};
+use pipeline_filter::{PipelineFilter, SkipMode};
 use publish::publish_metadata;
 use serde_json::Value;
 use service::{generate_client, generate_references};
+use usage::usage_report;
 use utils::{
-    fetch_dependent_pipelines, fetch_metadata_and_process, gen_ist,
+    fetch_dependent_pipelines, fetch_metadata_and_process, gen_ist, list_dependencies,
     refresh_internal_dependency_versions, register_db, register_package, system_check,
-    trigger_pipeline, update_pipeline,
+    trigger_pipeline, update_pipeline, ConfigSelection,
 };
 use IAMService::apis::configuration::Configuration as IAMConfiguration;
 use IAMService::apis::default_api::identity_validate_api_token;
@@ -28,12 +38,33 @@ use MetadataService::{
     get_configuration as get_metadata_configuration,
 };
 
+mod asyncapi;
+mod base_urls;
+mod catalogue;
+mod contract;
+mod dispatch_plan;
+mod environments;
 mod file_utils;
 mod generate;
+mod graph;
+mod graph_export;
+mod graphql;
+mod grpc;
+mod impact;
 mod init;
+mod lint_deps;
+mod orchestrator;
+mod pagination;
+mod pipeline_filter;
+mod pipeline_provider;
+mod preview;
 mod publish;
 mod refresher;
 mod service;
+mod services_toml;
+mod spec_diff;
+mod spec_lint;
+mod usage;
 mod utils;
 
 /// Command line interface for managing the application
@@ -51,53 +82,152 @@ enum Commands {
     Init,
     /// publishes the project metadata to the metadata service
     Publish {
-        #[clap(value_enum, default_value_t=Environment::Dev)]
-        env: Environment,
+        #[clap(default_value = "dev")]
+        env: EnvKey,
+        /// Publish even if the spec has breaking changes and the major version was not bumped
+        #[clap(long)]
+        allow_breaking: bool,
+        /// Also publish the operations this project calls on its dependencies
+        #[clap(long)]
+        with_usage: bool,
+        /// Publish to the preview env of a pull request, e.g. `--preview PR-123`. Needs a
+        /// `preview` url containing `{preview_id}` in services.toml
+        #[clap(long)]
+        preview: Option<String>,
     },
     /// Register a package
     Register {
-        #[clap(value_enum, default_value_t=Environment::Dev)]
-        env: Environment,
+        #[clap(default_value = "dev")]
+        env: EnvKey,
+    },
+    /// Configures a service to a project, interactively when no action is given
+    Config {
+        #[clap(subcommand)]
+        action: Option<ConfigAction>,
     },
-    /// Configures a service to a project
-    Config,
     /// This triggers the lowest set of components with zero depedencies. This will bubble up to run everything once again
-    SystemCheck { pipeline_token: String },
+    SystemCheck {
+        pipeline_token: String,
+        /// Dispatch the whole org wave by wave, waiting for each wave to pass before the next one
+        #[clap(long)]
+        orchestrate: bool,
+        /// Seconds between two polls of the running pipelines
+        #[clap(long, default_value = "30")]
+        poll_interval: u64,
+        /// Minutes a wave may run before its pending pipelines are reported as timed out
+        #[clap(long, default_value = "60")]
+        wave_timeout: u64,
+        /// Counts the runs that can't be looked up (Tekton) as passing, letting their
+        /// dependents run
+        #[clap(long, requires = "orchestrate")]
+        assume_untracked_pass: bool,
+        /// Env whose pipelines are run and statuses updated
+        #[clap(long, default_value = "stage")]
+        env: EnvKey,
+        #[clap(flatten)]
+        dry_run: DryRunArgs,
+    },
     /// Given the JWT secret , this generates a long live token that can be used to call inter service endpoints
     GenIST { jwt_secret: String },
     /// Finds out and triggers the dependent pipelines
     TriggerDependentPipelines {
         pipeline_token: String,
+        /// Names of the pipelines to skip, same as --skip
         #[clap(short, long, use_value_delimiter = true)]
         pipelines_to_skip: Option<Vec<String>>,
+        #[clap(flatten)]
+        filter: FilterArgs,
+        /// Env whose pipelines are run and statuses updated
+        #[clap(long, default_value = "stage")]
+        env: EnvKey,
+        #[clap(flatten)]
+        dry_run: DryRunArgs,
+        #[clap(flatten)]
+        wait: WaitArgs,
     },
     /// Finds out and triggers the dependent pipelines
-    TriggerPipeline { id: String, pipeline_token: String },
+    TriggerPipeline {
+        id: String,
+        pipeline_token: String,
+        /// Env whose pipeline is run and status updated
+        #[clap(long, default_value = "stage")]
+        env: EnvKey,
+        #[clap(flatten)]
+        dry_run: DryRunArgs,
+        #[clap(flatten)]
+        wait: WaitArgs,
+    },
     /// Connect to an environment and generate the client
     Connect {
-        #[clap(value_enum, default_value_t=Environment::Dev)]
-        env: Environment,
+        #[clap(default_value = "dev")]
+        env: EnvKey,
+        /// Connect to the preview deployments of a pull request, falling back to `env`
+        #[clap(long)]
+        preview: Option<String>,
     },
     /// this updates the pipeline statuses for components except for the DBs
     UpdatePipeline {
-        #[clap(value_enum, default_value_t=Environment::Dev)]
-        env: Environment,
+        #[clap(default_value = "dev")]
+        env: EnvKey,
         #[clap(value_parser)]
         status: String,
     },
     /// this updates the DBs pipeline status
     UpdateDBPipeline {
-        #[clap(value_enum, default_value_t=Environment::Dev)]
-        env: Environment,
+        #[clap(default_value = "dev")]
+        env: EnvKey,
         #[clap(value_parser)]
         status: String,
         #[clap(value_parser)]
         slug: String,
     },
+    /// Lists the consumers affected by the changes in the local spec compared to the published one
+    Impact {
+        #[clap(default_value = "stage")]
+        env: EnvKey,
+        /// Path to the local spec, fetched from the `urls` of this env when not given
+        #[clap(long)]
+        spec: Option<String>,
+    },
+    /// Scans the sources for the operations called on the generated clients
+    Usage {
+        /// Print the usage set as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Flags declared service and internal package dependencies that are never used
+    LintDeps,
+    /// Searches and describes the services of the organization
+    Services {
+        #[clap(subcommand)]
+        action: ServicesAction,
+    },
+    /// Manages the pipelines of the organization
+    Pipelines {
+        #[clap(subcommand)]
+        action: PipelinesAction,
+    },
+    /// Exports the dependency graph of the organization, colored by kind and pipeline status
+    Graph {
+        #[clap(long, value_enum, default_value = "dot")]
+        format: GraphFormat,
+        /// Env whose packages, services and pipeline statuses are exported
+        #[clap(long, default_value = "stage")]
+        env: EnvKey,
+        /// Only exports this node and its dependencies, e.g. `@gingersociety/IAMService`
+        #[clap(long)]
+        upstream_of: Option<String>,
+        /// Only exports this node and its dependents. With --upstream-of, only the nodes in between
+        #[clap(long)]
+        downstream_of: Option<String>,
+        /// Dependency levels followed from --upstream-of / --downstream-of
+        #[clap(long)]
+        depth: Option<usize>,
+    },
     /// Generates references to portals
     Refer {
-        #[clap(value_enum, default_value_t=Environment::Dev)]
-        env: Environment,
+        #[clap(default_value = "dev")]
+        env: EnvKey,
     },
     /// Generate a client for a specified language
     Generate {
@@ -114,6 +244,114 @@ enum Commands {
     Refresh,
 }
 
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Adds services or portals, e.g. `config add @gingersociety/IAMService`
+    Add {
+        #[clap(required = true)]
+        services: Vec<String>,
+    },
+    /// Removes services or portals
+    Remove {
+        #[clap(required = true)]
+        services: Vec<String>,
+    },
+    /// Lists the configured services and portals with their env urls
+    List,
+    /// Refreshes the env urls of the configured services without changing the selection
+    Sync,
+}
+
+#[derive(Subcommand)]
+enum ServicesAction {
+    /// Finds services by name, type or description
+    Search { query: String },
+    /// Shows the description, owners, links, the version and base URLs of every env, and the endpoints of a service, e.g. `@gingersociety/IAMService`
+    Describe { slug: String },
+}
+
+#[derive(Subcommand)]
+enum PipelinesAction {
+    /// Re-dispatches the failing pipelines whose dependencies are all passing
+    RetryFailed {
+        pipeline_token: String,
+        /// Env whose pipeline statuses are read and pipelines run
+        #[clap(long, default_value = "stage")]
+        env: EnvKey,
+        /// Then runs everything downstream of them wave by wave, implies --wait
+        #[clap(long)]
+        cascade: bool,
+        #[clap(flatten)]
+        dry_run: DryRunArgs,
+        #[clap(flatten)]
+        wait: WaitArgs,
+    },
+}
+
+#[derive(Args)]
+struct FilterArgs {
+    /// Pipelines not to dispatch: globs on the name or slug (`*Portal`),
+    /// `kind:<package|service|portal|db>` or `tag:<tag>`
+    #[clap(long, use_value_delimiter = true)]
+    skip: Vec<String>,
+    /// Only dispatches the matching pipelines, same patterns as --skip
+    #[clap(long, use_value_delimiter = true)]
+    only: Vec<String>,
+    /// Whether the dependents of the skipped pipelines still run
+    #[clap(long, value_enum, default_value = "node")]
+    skip_mode: SkipMode,
+}
+
+#[derive(Args)]
+struct DryRunArgs {
+    /// Prints the pipelines that would be dispatched, in order and with the dependency path
+    /// that selected them, without dispatching them or updating their status
+    #[clap(long)]
+    dry_run: bool,
+    /// Prints the dry run plan as JSON
+    #[clap(long, requires = "dry_run")]
+    json: bool,
+}
+
+impl DryRunArgs {
+    fn output(&self) -> Option<PlanOutput> {
+        self.dry_run.then(|| PlanOutput::new(self.json))
+    }
+}
+
+#[derive(Args)]
+struct WaitArgs {
+    /// Waits for the dispatched runs to finish, updating their status in Metadata, and fails if
+    /// one of them does not pass
+    #[clap(long)]
+    wait: bool,
+    /// Seconds between two polls of the runs
+    #[clap(long, default_value = "30", requires = "wait")]
+    poll_interval: u64,
+    /// Minutes to wait for the runs before reporting them as timed out
+    #[clap(long, default_value = "60", requires = "wait")]
+    timeout: u64,
+    /// Counts the runs that can't be looked up (Tekton) as passing instead of failing
+    #[clap(long)]
+    assume_untracked_pass: bool,
+}
+
+impl WaitArgs {
+    fn options(&self, env: &EnvKey) -> Option<OrchestrateOptions> {
+        self.wait.then(|| self.forced_options(env))
+    }
+
+    /// For the modes that always wait
+    fn forced_options(&self, env: &EnvKey) -> OrchestrateOptions {
+        OrchestrateOptions {
+            env: env.clone(),
+            poll_interval: Duration::from_secs(self.poll_interval),
+            timeout: Duration::from_secs(self.timeout * 60),
+            assume_untracked_pass: self.assume_untracked_pass,
+        }
+    }
+}
+
 #[tokio::main]
 async fn check_session_gurad(
     cli: CLI,
@@ -126,7 +364,13 @@ async fn check_session_gurad(
     match identity_validate_api_token(&iam_config).await {
         Ok(response) => {
             match &cli.command {
-                Commands::TriggerPipeline { id, pipeline_token } => {
+                Commands::TriggerPipeline {
+                    id,
+                    pipeline_token,
+                    env,
+                    dry_run,
+                    wait,
+                } => {
                     println!("{:?} , {:?}", pipeline_token, id);
                     trigger_pipeline(
                         config_path,
@@ -134,33 +378,137 @@ async fn check_session_gurad(
                         &metadata_config,
                         pipeline_token,
                         id,
+                        env,
+                        dry_run.output(),
+                        wait.options(env),
                     )
                     .await;
                 }
                 Commands::GenIST { jwt_secret } => gen_ist(package_path, jwt_secret),
-                Commands::SystemCheck { pipeline_token } => {
-                    system_check(config_path, &iam_config, &metadata_config, pipeline_token).await
+                Commands::SystemCheck {
+                    pipeline_token,
+                    orchestrate,
+                    poll_interval,
+                    wave_timeout,
+                    assume_untracked_pass,
+                    env,
+                    dry_run,
+                } => {
+                    if *orchestrate {
+                        orchestrate_system_check(
+                            config_path,
+                            &metadata_config,
+                            pipeline_token,
+                            OrchestrateOptions {
+                                env: env.clone(),
+                                poll_interval: Duration::from_secs(*poll_interval),
+                                timeout: Duration::from_secs(wave_timeout * 60),
+                                assume_untracked_pass: *assume_untracked_pass,
+                            },
+                            dry_run.output(),
+                        )
+                        .await
+                    } else {
+                        system_check(
+                            config_path,
+                            &iam_config,
+                            &metadata_config,
+                            pipeline_token,
+                            env,
+                            dry_run.output(),
+                        )
+                        .await
+                    }
                 }
                 Commands::Refresh => {
                     refresh_internal_dependency_versions(config_path, &metadata_config).await
                 }
+                Commands::Pipelines { action } => match action {
+                    PipelinesAction::RetryFailed {
+                        pipeline_token,
+                        env,
+                        cascade,
+                        dry_run,
+                        wait,
+                    } => {
+                        let wait = match cascade {
+                            true => Some(wait.forced_options(env)),
+                            false => wait.options(env),
+                        };
+                        retry_failed(
+                            config_path,
+                            &metadata_config,
+                            pipeline_token,
+                            env,
+                            *cascade,
+                            wait,
+                            dry_run.output(),
+                        )
+                        .await
+                    }
+                },
+                Commands::Graph {
+                    format,
+                    env,
+                    upstream_of,
+                    downstream_of,
+                    depth,
+                } => {
+                    let selection = GraphSelection {
+                        upstream_of: upstream_of.clone(),
+                        downstream_of: downstream_of.clone(),
+                        depth: *depth,
+                    };
+                    export_graph(config_path, metadata_config, env, *format, &selection).await
+                }
                 Commands::TriggerDependentPipelines {
                     pipeline_token,
                     pipelines_to_skip,
+                    filter,
+                    env,
+                    dry_run,
+                    wait,
                 } => {
-                    let pipeline_ids_to_skip = pipelines_to_skip.clone().unwrap_or_else(Vec::new);
-                    println!("{:?}", pipeline_ids_to_skip);
+                    let mut skip = pipelines_to_skip.clone().unwrap_or_else(Vec::new);
+                    skip.extend(filter.skip.iter().cloned());
+                    let filter = match PipelineFilter::new(&skip, &filter.only, filter.skip_mode) {
+                        Ok(filter) => filter,
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            exit(1);
+                        }
+                    };
                     fetch_dependent_pipelines(
                         config_path,
                         &iam_config,
                         &metadata_config,
                         pipeline_token,
-                        pipeline_ids_to_skip,
+                        filter,
+                        env,
+                        dry_run.output(),
+                        wait.options(env),
                     )
                     .await;
                 }
-                Commands::Config {} => {
-                    fetch_metadata_and_process(config_path, &iam_config, &metadata_config).await;
+                Commands::Config { action } => {
+                    let selection = match action {
+                        None => ConfigSelection::Interactive,
+                        Some(ConfigAction::Add { services }) => {
+                            ConfigSelection::Add(services.clone())
+                        }
+                        Some(ConfigAction::Remove { services }) => {
+                            ConfigSelection::Remove(services.clone())
+                        }
+                        Some(ConfigAction::List) => unreachable!("listed before the session check"),
+                        Some(ConfigAction::Sync) => ConfigSelection::Sync,
+                    };
+                    fetch_metadata_and_process(
+                        config_path,
+                        &iam_config,
+                        &metadata_config,
+                        selection,
+                    )
+                    .await;
                 }
                 Commands::Register { env } => {
                     if !Path::new("db-compose.toml").exists() {
@@ -177,9 +525,23 @@ async fn check_session_gurad(
                         register_db(&metadata_config, releaser_path).await;
                     }
                 }
-                Commands::Connect { env } => {
-                    generate_client(config_path, env.clone(), metadata_config).await
+                Commands::Connect { env, preview } => {
+                    generate_client(config_path, env.clone(), preview.clone(), metadata_config)
+                        .await
+                }
+                Commands::Impact { env, spec } => {
+                    impact_report(config_path, env.clone(), metadata_config, spec).await
                 }
+                Commands::Usage { json } => usage_report(config_path, *json),
+                Commands::LintDeps => lint_dependencies(config_path),
+                Commands::Services { action } => match action {
+                    ServicesAction::Search { query } => {
+                        search_services(config_path, metadata_config, query).await
+                    }
+                    ServicesAction::Describe { slug } => {
+                        describe_service(metadata_config, slug).await
+                    }
+                },
                 Commands::Refer { env } => generate_references(config_path, env.clone()),
                 Commands::Init => initialize(config_path),
                 Commands::Generate {
@@ -190,13 +552,24 @@ async fn check_session_gurad(
                 } => {
                     generate_arbitrary_client(swagger_path, lang.clone(), server_url, out_folder);
                 }
-                Commands::Publish { env } => {
+                Commands::Publish {
+                    env,
+                    allow_breaking,
+                    with_usage,
+                    preview,
+                } => {
+                    let env = match preview {
+                        Some(id) => EnvKey::preview(id),
+                        None => env.clone(),
+                    };
                     publish_metadata(
                         config_path,
-                        env.clone(),
+                        env,
                         metadata_config,
                         releaser_path,
                         package_path,
+                        *allow_breaking,
+                        *with_usage,
                     )
                     .await
                 }
@@ -256,6 +629,15 @@ fn main() {
     let package_path = Path::new("metadata.toml");
     let releaser_path = Path::new("releaser.toml");
 
+    // Only reads services.toml, no need for a session
+    if let Commands::Config {
+        action: Some(ConfigAction::List),
+    } = &cli.command
+    {
+        list_dependencies(config_path);
+        return;
+    }
+
     let token = get_token_from_file_storage();
 
     let iam_config: IAMConfiguration = get_iam_configuration(Some(token.clone()));
@@ -0,0 +1,609 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::Path,
+    process::exit,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use reqwest::{Client, RequestBuilder};
+use serde_json::Value as JsonValue;
+use tokio::time::{sleep, Instant};
+use MetadataService::apis::configuration::Configuration as MetadataConfiguration;
+
+use crate::{
+    dispatch_plan::{path_from_first_wave, DispatchPlan, PlanOutput, PlannedPipeline},
+    environments::{EnvKey, Environments},
+    graph::{load_dependency_graph, DependencyGraph, Layers, Node},
+    pipeline_provider::Pipeline,
+    services_toml::read_services_config,
+    utils::{dispatch_pipeline, dispatch_pipelines, report_cycles, set_pipeline_status},
+};
+
+/// Pipeline statuses recorded in Metadata, see `set_pipeline_status`
+const PASSING: &str = "passing";
+const FAILING: &str = "failing";
+
+/// Runs created this long before the dispatch are still matched, to absorb clock skew
+const CLOCK_SKEW_SECS: i64 = 30;
+
+pub struct OrchestrateOptions {
+    /// Env whose pipelines are run and whose statuses are updated
+    pub env: EnvKey,
+    pub poll_interval: Duration,
+    /// Per wave
+    pub timeout: Duration,
+    /// Counts the runs that can't be looked up as passing instead of failing the command
+    pub assume_untracked_pass: bool,
+}
+
+impl OrchestrateOptions {
+    /// Whether `status` lets the downstream pipelines run and the command succeed
+    fn accepts(&self, status: &NodeStatus) -> bool {
+        status.is_ok() || (self.assume_untracked_pass && *status == NodeStatus::Untracked)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeStatus {
+    Pending,
+    Running,
+    Passed,
+    Failed(String),
+    /// Not dispatched because an upstream pipeline did not pass
+    Skipped,
+    /// Part of or blocked by a dependency cycle
+    Cycle,
+    TimedOut,
+    /// Dispatched to a provider whose runs can't be looked up (Tekton), its outcome is unknown
+    Untracked,
+}
+
+impl fmt::Display for NodeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeStatus::Pending => write!(f, "pending"),
+            NodeStatus::Running => write!(f, "running"),
+            NodeStatus::Passed => write!(f, "passed"),
+            NodeStatus::Failed(reason) => write!(f, "failed ({})", reason),
+            NodeStatus::Skipped => write!(f, "skipped, upstream did not pass"),
+            NodeStatus::Cycle => write!(f, "not run, dependency cycle"),
+            NodeStatus::TimedOut => write!(f, "timed out"),
+            NodeStatus::Untracked => write!(f, "dispatched, not tracked"),
+        }
+    }
+}
+
+impl NodeStatus {
+    fn is_ok(&self) -> bool {
+        *self == NodeStatus::Passed
+    }
+
+    fn colored(&self) -> colored::ColoredString {
+        match self {
+            NodeStatus::Passed => self.to_string().green(),
+            NodeStatus::Failed(_) | NodeStatus::TimedOut => self.to_string().red(),
+            _ => self.to_string().yellow(),
+        }
+    }
+}
+
+struct Tracked {
+    node: Node,
+    pipeline: Pipeline,
+    dispatched_at: DateTime<Utc>,
+    run_id: Option<String>,
+}
+
+async fn get_json(request: RequestBuilder) -> Option<JsonValue> {
+    let response = request.send().await.ok()?;
+    if !response.status().is_success() {
+        eprintln!("GET {} failed: {}", response.url(), response.status());
+        return None;
+    }
+    response.json().await.ok()
+}
+
+/// The run created by a dispatch: the oldest one created after it. Runs created up to
+/// `CLOCK_SKEW_SECS` before it are only considered when there are none, the latest first,
+/// since they may as well be runs someone else started
+fn pick_run(runs: Vec<(String, DateTime<Utc>)>, dispatched_at: DateTime<Utc>) -> Option<String> {
+    let since = dispatched_at - chrono::Duration::seconds(CLOCK_SKEW_SECS);
+    let (after, skewed): (Vec<_>, Vec<_>) = runs
+        .into_iter()
+        .filter(|(_, created_at)| *created_at >= since)
+        .partition(|(_, created_at)| *created_at >= dispatched_at);
+    match after.into_iter().min_by_key(|(_, created_at)| *created_at) {
+        Some((id, _)) => Some(id),
+        None => skewed
+            .into_iter()
+            .max_by_key(|(_, created_at)| *created_at)
+            .map(|(id, _)| id),
+    }
+}
+
+async fn find_run(
+    client: &Client,
+    pipeline_token: &str,
+    tracked: &Tracked,
+    env: &EnvKey,
+) -> Option<String> {
+    let provider = tracked.pipeline.provider();
+    let token = tracked.pipeline.token(pipeline_token);
+    let runs = get_json(provider.runs_request(client, &token, env)?).await?;
+    pick_run(provider.parse_runs(&runs), tracked.dispatched_at)
+}
+
+/// `None` while the run is queued / in progress
+async fn run_conclusion(
+    client: &Client,
+    pipeline_token: &str,
+    tracked: &Tracked,
+    run_id: &str,
+) -> Option<NodeStatus> {
+    let provider = tracked.pipeline.provider();
+    let token = tracked.pipeline.token(pipeline_token);
+    let run = get_json(provider.run_request(client, &token, run_id)).await?;
+    match provider.parse_run(&run)? {
+        Ok(()) => Some(NodeStatus::Passed),
+        Err(conclusion) => Some(NodeStatus::Failed(conclusion)),
+    }
+}
+
+fn print_status_table(
+    graph: &DependencyGraph,
+    waves: &[Vec<String>],
+    statuses: &BTreeMap<String, NodeStatus>,
+    options: &OrchestrateOptions,
+) {
+    let wave_of: BTreeMap<&String, usize> = waves
+        .iter()
+        .enumerate()
+        .flat_map(|(index, wave)| wave.iter().map(move |slug| (slug, index + 1)))
+        .collect();
+    let width = statuses.keys().map(|slug| slug.len()).max().unwrap_or(0);
+
+    println!(
+        "\n{:width$}  {:10}  {:4}  {}",
+        "Pipeline",
+        "Kind",
+        "Wave",
+        "Status",
+        width = width
+    );
+    for (slug, status) in statuses {
+        println!(
+            "{:width$}  {:10}  {:4}  {}",
+            slug,
+            graph
+                .get(slug)
+                .map(|node| node.kind.to_string())
+                .unwrap_or_default(),
+            wave_of
+                .get(slug)
+                .map(|wave| wave.to_string())
+                .unwrap_or("-".to_string()),
+            status.colored(),
+            width = width
+        );
+    }
+
+    let untracked: Vec<&String> = statuses
+        .iter()
+        .filter(|(_, status)| **status == NodeStatus::Untracked)
+        .map(|(slug, _)| slug)
+        .collect();
+    if !untracked.is_empty() {
+        let outcome = match options.assume_untracked_pass {
+            true => "assumed to pass (--assume-untracked-pass)",
+            false => "counted as not passing, use --assume-untracked-pass to ignore them",
+        };
+        println!(
+            "\n{} {:?}, {}",
+            "Dispatched but not tracked:".yellow(),
+            untracked,
+            outcome
+        );
+    }
+}
+
+/// Dispatches the pipelines of the whole org wave by wave, a wave only starts once every
+/// pipeline of the previous one passed. Pipelines downstream of a failure are skipped.
+pub async fn orchestrate_system_check(
+    config_path: &Path,
+    metadata_config: &MetadataConfiguration,
+    pipeline_token: &String,
+    options: OrchestrateOptions,
+    dry_run: Option<PlanOutput>,
+) {
+    let config = read_services_config(config_path).unwrap();
+    let env = &options.env;
+    if let Err(e) = Environments::load(config_path).ensure_known(env) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+
+    let graph = match load_dependency_graph(metadata_config, &config.organization_id, env).await {
+        Ok(graph) => graph,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+
+    let plan = graph.layers();
+
+    if let Some(output) = dry_run {
+        plan_waves(&graph, &plan, env).print(output);
+        return;
+    }
+
+    run_waves(metadata_config, pipeline_token, &graph, &plan, &options).await;
+}
+
+/// What `run_waves` would dispatch, for `--dry-run`
+fn plan_waves(graph: &DependencyGraph, plan: &Layers, env: &EnvKey) -> DispatchPlan {
+    let mut dispatch_plan = DispatchPlan::new();
+    for (index, wave) in plan.layers.iter().enumerate() {
+        for slug in wave {
+            let path = path_from_first_wave(graph, &plan.layers, slug);
+            dispatch_plan.dispatch(PlannedPipeline::new(
+                graph.get(slug).unwrap(),
+                env,
+                index + 1,
+                path,
+            ));
+        }
+    }
+    for slug in plan.cycles.iter().flatten() {
+        dispatch_plan.skip(slug, "part of a dependency cycle");
+    }
+    for slug in &plan.blocked {
+        dispatch_plan.skip(slug, "blocked by a dependency cycle");
+    }
+    dispatch_plan
+}
+
+/// Runs the layers of `plan` one wave at a time, skipping the pipelines whose upstream did not
+/// pass. Dependencies outside of the plan are assumed to be passing.
+async fn run_waves(
+    metadata_config: &MetadataConfiguration,
+    pipeline_token: &String,
+    graph: &DependencyGraph,
+    plan: &Layers,
+    options: &OrchestrateOptions,
+) {
+    report_cycles(&plan.cycles);
+
+    let mut statuses: BTreeMap<String, NodeStatus> = BTreeMap::new();
+    for slug in plan.cycles.iter().flatten().chain(plan.blocked.iter()) {
+        statuses.insert(slug.clone(), NodeStatus::Cycle);
+    }
+    for slug in plan.layers.iter().flatten() {
+        statuses.insert(slug.clone(), NodeStatus::Pending);
+    }
+
+    let client = Client::new();
+    for (index, wave) in plan.layers.iter().enumerate() {
+        println!("\n{} {} : {:?}", "Wave".bold(), index + 1, wave);
+
+        let mut ready = vec![];
+        for slug in wave {
+            let upstream_ok = graph
+                .dependencies_of(slug)
+                .iter()
+                .all(|dep| statuses.get(*dep).map_or(true, |s| options.accepts(s)));
+            if upstream_ok {
+                ready.push(graph.get(slug).unwrap());
+            } else {
+                statuses.insert(slug.clone(), NodeStatus::Skipped);
+            }
+        }
+
+        let mut tracked = dispatch_all(
+            &client,
+            metadata_config,
+            pipeline_token,
+            &options.env,
+            &ready,
+            &mut statuses,
+        )
+        .await;
+        wait_for_runs(
+            &client,
+            metadata_config,
+            pipeline_token,
+            options,
+            &mut tracked,
+            &mut statuses,
+        )
+        .await;
+    }
+
+    print_status_table(graph, &plan.layers, &statuses, options);
+
+    if statuses.values().any(|status| !options.accepts(status)) {
+        exit(1);
+    }
+}
+
+/// Re-dispatches the pipelines `failing` in Metadata whose dependencies are all `passing`.
+/// With `cascade`, everything downstream of them is then run wave by wave, which needs `wait`.
+pub async fn retry_failed(
+    config_path: &Path,
+    metadata_config: &MetadataConfiguration,
+    pipeline_token: &String,
+    env: &EnvKey,
+    cascade: bool,
+    wait: Option<OrchestrateOptions>,
+    dry_run: Option<PlanOutput>,
+) {
+    let config = read_services_config(config_path).unwrap();
+    if let Err(e) = Environments::load(config_path).ensure_known(env) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+
+    let graph = match load_dependency_graph(metadata_config, &config.organization_id, env).await {
+        Ok(graph) => graph,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+
+    let status_of = |slug: &str| graph.get(slug).and_then(|node| node.status.as_deref());
+    let mut retry: BTreeSet<String> = BTreeSet::new();
+    let mut not_ready: Vec<(String, String)> = vec![];
+    for node in graph.nodes() {
+        if node.status.as_deref() != Some(FAILING) {
+            continue;
+        }
+        let upstream: Vec<String> = graph
+            .dependencies_of(&node.slug)
+            .into_iter()
+            .filter(|dep| status_of(dep) != Some(PASSING))
+            .map(|dep| format!("{} ({})", dep, status_of(dep).unwrap_or("no status")))
+            .collect();
+        if upstream.is_empty() {
+            retry.insert(node.slug.clone());
+        } else {
+            let reason = format!("upstream not passing: {}", upstream.join(", "));
+            not_ready.push((node.slug.clone(), reason));
+        }
+    }
+
+    let plan = if cascade {
+        let mut scope = retry.clone();
+        for slug in &retry {
+            scope.extend(graph.downstream_of(slug));
+        }
+        graph.layers_of(&scope)
+    } else {
+        Layers {
+            layers: vec![retry.iter().cloned().collect()],
+            ..Layers::default()
+        }
+    };
+
+    if let Some(output) = dry_run {
+        let mut dispatch_plan = plan_waves(&graph, &plan, env);
+        for (slug, reason) in &not_ready {
+            dispatch_plan.skip(slug, reason.clone());
+        }
+        dispatch_plan.print(output);
+        return;
+    }
+
+    for (slug, reason) in &not_ready {
+        println!("Not retrying {} : {}", slug, reason);
+    }
+    if retry.is_empty() {
+        println!("No failed pipelines to retry in {}", env);
+        return;
+    }
+
+    match wait {
+        Some(options) if cascade => {
+            run_waves(metadata_config, pipeline_token, &graph, &plan, &options).await
+        }
+        Some(options) => {
+            let nodes: Vec<&Node> = retry.iter().filter_map(|slug| graph.get(slug)).collect();
+            dispatch_and_wait(metadata_config, pipeline_token, &nodes, options).await
+        }
+        None => {
+            let nodes: Vec<&Node> = retry.iter().filter_map(|slug| graph.get(slug)).collect();
+            dispatch_pipelines(&Client::new(), metadata_config, pipeline_token, &nodes, env).await
+        }
+    }
+}
+
+/// Dispatches the pipelines, waits for their runs and exits with an error if one of them does
+/// not pass
+pub async fn dispatch_and_wait(
+    metadata_config: &MetadataConfiguration,
+    pipeline_token: &String,
+    nodes: &[&Node],
+    options: OrchestrateOptions,
+) {
+    let client = Client::new();
+    let mut statuses: BTreeMap<String, NodeStatus> = BTreeMap::new();
+    let mut tracked = dispatch_all(
+        &client,
+        metadata_config,
+        pipeline_token,
+        &options.env,
+        nodes,
+        &mut statuses,
+    )
+    .await;
+    wait_for_runs(
+        &client,
+        metadata_config,
+        pipeline_token,
+        &options,
+        &mut tracked,
+        &mut statuses,
+    )
+    .await;
+
+    let mut graph = DependencyGraph::new();
+    for node in nodes {
+        graph.add_node((*node).clone());
+    }
+    let wave: Vec<String> = nodes.iter().map(|node| node.slug.clone()).collect();
+    print_status_table(&graph, &[wave], &statuses, &options);
+
+    if statuses.values().any(|status| !options.accepts(status)) {
+        exit(1);
+    }
+}
+
+/// Dispatches the nodes and returns the runs that can be tracked
+async fn dispatch_all(
+    client: &Client,
+    metadata_config: &MetadataConfiguration,
+    pipeline_token: &String,
+    env: &EnvKey,
+    nodes: &[&Node],
+    statuses: &mut BTreeMap<String, NodeStatus>,
+) -> BTreeMap<String, Tracked> {
+    let mut tracked = BTreeMap::new();
+    // Nodes sharing a run, like the schemas of a DB repo, follow the run dispatched first
+    let mut dispatched: BTreeMap<String, DateTime<Utc>> = BTreeMap::new();
+    for node in nodes {
+        let run_key = node.pipeline.as_ref().map(|pipeline| pipeline.run_key(env));
+        let dispatched_at = match run_key.as_ref().and_then(|key| dispatched.get(key)) {
+            Some(dispatched_at) => {
+                set_pipeline_status(metadata_config, node, env, "waiting").await;
+                *dispatched_at
+            }
+            None => {
+                let dispatched_at = Utc::now();
+                if !dispatch_pipeline(client, metadata_config, pipeline_token, node, env).await {
+                    statuses.insert(
+                        node.slug.clone(),
+                        NodeStatus::Failed("not dispatched".to_string()),
+                    );
+                    continue;
+                }
+                if let Some(key) = run_key {
+                    dispatched.insert(key, dispatched_at);
+                }
+                dispatched_at
+            }
+        };
+        let pipeline = node.pipeline.clone().unwrap();
+        if pipeline
+            .provider()
+            .runs_request(client, &pipeline.token(pipeline_token), env)
+            .is_none()
+        {
+            statuses.insert(node.slug.clone(), NodeStatus::Untracked);
+            continue;
+        }
+        tracked.insert(
+            node.slug.clone(),
+            Tracked {
+                node: (*node).clone(),
+                pipeline,
+                dispatched_at,
+                run_id: None,
+            },
+        );
+        statuses.insert(node.slug.clone(), NodeStatus::Running);
+    }
+    tracked
+}
+
+/// Polls the tracked runs until they are all done or `options.timeout` elapses. Their status
+/// in Metadata follows: `running` once the run is found, then `passing` or `failing`.
+async fn wait_for_runs(
+    client: &Client,
+    metadata_config: &MetadataConfiguration,
+    pipeline_token: &String,
+    options: &OrchestrateOptions,
+    tracked: &mut BTreeMap<String, Tracked>,
+    statuses: &mut BTreeMap<String, NodeStatus>,
+) {
+    let env = &options.env;
+    let started = Instant::now();
+    while statuses
+        .iter()
+        .any(|(slug, status)| tracked.contains_key(slug) && *status == NodeStatus::Running)
+    {
+        if started.elapsed() > options.timeout {
+            for (slug, status) in statuses.iter_mut() {
+                if tracked.contains_key(slug) && *status == NodeStatus::Running {
+                    *status = NodeStatus::TimedOut;
+                }
+            }
+            break;
+        }
+        sleep(options.poll_interval).await;
+
+        for (slug, run) in tracked.iter_mut() {
+            if statuses.get(slug) != Some(&NodeStatus::Running) {
+                continue;
+            }
+            if run.run_id.is_none() {
+                run.run_id = find_run(client, pipeline_token, run, env).await;
+                if let Some(run_id) = &run.run_id {
+                    println!("{} : run {} started", slug, run_id);
+                    set_pipeline_status(metadata_config, &run.node, env, "running").await;
+                }
+            }
+            let run_id = match run.run_id.clone() {
+                Some(run_id) => run_id,
+                None => continue,
+            };
+            if let Some(status) = run_conclusion(client, pipeline_token, run, &run_id).await {
+                println!("{} : {}", slug, status.colored());
+                let metadata_status = if status.is_ok() { PASSING } else { FAILING };
+                set_pipeline_status(metadata_config, &run.node, env, metadata_status).await;
+                statuses.insert(slug.clone(), status);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn runs(created: &[(&str, i64)]) -> Vec<(String, DateTime<Utc>)> {
+        created
+            .iter()
+            .map(|(id, secs)| (id.to_string(), at(*secs)))
+            .collect()
+    }
+
+    #[test]
+    fn the_oldest_run_after_the_dispatch_is_ours() {
+        assert_eq!(
+            pick_run(
+                runs(&[("earlier", -10), ("ours", 3), ("next", 40), ("old", -600)]),
+                at(0)
+            ),
+            Some("ours".to_string())
+        );
+    }
+
+    #[test]
+    fn runs_within_the_clock_skew_are_a_fallback() {
+        assert_eq!(
+            pick_run(
+                runs(&[("skewed", -5), ("before", -20), ("old", -600)]),
+                at(0)
+            ),
+            Some("skewed".to_string())
+        );
+        assert_eq!(pick_run(runs(&[("old", -600)]), at(0)), None);
+    }
+}
@@ -0,0 +1,53 @@
+use std::future::Future;
+
+/// Page size used for the paginated metadata list calls
+pub const PAGE_SIZE: usize = 50;
+
+/// Upper bound on the pages fetched, in case an endpoint keeps returning new items
+const MAX_PAGES: usize = 1000;
+
+/// Fetches pages (starting at 1) until one comes back empty and returns all the items, so
+/// that nothing past the first page silently goes missing. A short page doesn't end the
+/// listing since the server may cap `page_size` below [`PAGE_SIZE`], and a page repeating
+/// the previous one does, for endpoints ignoring `page_number`. `fetch` is given the page
+/// number:
+///
+/// ```ignore
+/// collect_pages(|page| {
+///     metadata_get_services_and_envs(
+///         metadata_config,
+///         MetadataGetServicesAndEnvsParams {
+///             page_number: Some(page.to_string()),
+///             page_size: Some(PAGE_SIZE.to_string()),
+///             org_id: org_id.clone(),
+///         },
+///     )
+/// })
+/// .await
+/// ```
+pub async fn collect_pages<T, E, F, Fut>(mut fetch: F) -> Result<Vec<T>, E>
+where
+    T: PartialEq,
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, E>>,
+{
+    let mut items: Vec<T> = vec![];
+    let mut previous_count = 0;
+    for page in 1..=MAX_PAGES {
+        let batch = fetch(page).await?;
+        if batch.is_empty() || repeats_last_page(&items, previous_count, &batch) {
+            return Ok(items);
+        }
+        previous_count = batch.len();
+        items.extend(batch);
+    }
+    eprintln!(
+        "Stopped paginating after {} pages, the results may be incomplete",
+        MAX_PAGES
+    );
+    Ok(items)
+}
+
+fn repeats_last_page<T: PartialEq>(items: &[T], previous_count: usize, batch: &[T]) -> bool {
+    batch.len() == previous_count && items[items.len() - previous_count..] == *batch
+}
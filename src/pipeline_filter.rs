@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use clap::ValueEnum;
+
+use crate::graph::{DependencyGraph, Node, NodeKind};
+
+/// What skipping a pipeline does to the pipelines depending on it
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SkipMode {
+    /// Only the pipeline is skipped, its dependents still run
+    Node,
+    /// The pipeline and everything downstream of it are skipped
+    Subtree,
+}
+
+/// A `--skip` / `--only` value: `kind:<package|service|portal|db|cache|queue>`, `tag:<tag>`
+/// or a glob (`*` and `?`) on the name or the slug, e.g. `*Portal` or `@gingersociety/IAM*`
+#[derive(Debug, Clone)]
+enum Pattern {
+    Kind(NodeKind),
+    Tag(String),
+    Glob(String),
+}
+
+impl Pattern {
+    fn parse(value: &str) -> Result<Pattern, String> {
+        if let Some(kind) = value.strip_prefix("kind:") {
+            let kind = match kind {
+                "package" => NodeKind::Package,
+                "service" => NodeKind::Service,
+                "portal" => NodeKind::Portal,
+                "db" => NodeKind::DbSchema,
+                "cache" => NodeKind::Cache,
+                "queue" => NodeKind::MessageQueue,
+                _ => {
+                    return Err(format!(
+                        "Unknown kind {:?} in {:?}, expected package, service, portal, db, \
+                         cache or queue",
+                        kind, value
+                    ))
+                }
+            };
+            return Ok(Pattern::Kind(kind));
+        }
+        if let Some(tag) = value.strip_prefix("tag:") {
+            return Ok(Pattern::Tag(tag.to_string()));
+        }
+        Ok(Pattern::Glob(value.to_string()))
+    }
+
+    fn matches(&self, node: &Node) -> bool {
+        match self {
+            Pattern::Kind(kind) => node.kind == *kind,
+            Pattern::Tag(tag) => node
+                .pipeline
+                .as_ref()
+                .and_then(|pipeline| pipeline.config.tags.as_ref())
+                .map_or(false, |tags| tags.contains(tag)),
+            Pattern::Glob(glob) => {
+                let name = node.slug.rsplit('/').next().unwrap_or(&node.slug);
+                glob_match(glob, name) || glob_match(glob, &node.slug)
+            }
+        }
+    }
+}
+
+/// `*` matches any run of characters, `?` a single one
+fn glob_match(glob: &str, value: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    // matched[j]: whether the glob read so far matches the first j characters of the value
+    let mut matched = vec![false; value.len() + 1];
+    matched[0] = true;
+    for token in &glob {
+        let mut next = vec![false; value.len() + 1];
+        match token {
+            '*' => {
+                let mut any = false;
+                for j in 0..=value.len() {
+                    any = any || matched[j];
+                    next[j] = any;
+                }
+            }
+            _ => {
+                for j in 1..=value.len() {
+                    next[j] = matched[j - 1] && (*token == '?' || *token == value[j - 1]);
+                }
+            }
+        }
+        matched = next;
+    }
+    matched[value.len()]
+}
+
+/// Narrows down the pipelines a command dispatches
+pub struct PipelineFilter {
+    skip: Vec<(String, Pattern)>,
+    only: Vec<(String, Pattern)>,
+    mode: SkipMode,
+}
+
+impl PipelineFilter {
+    pub fn new(skip: &[String], only: &[String], mode: SkipMode) -> Result<PipelineFilter, String> {
+        let parse = |values: &[String]| {
+            values
+                .iter()
+                .map(|value| Pattern::parse(value).map(|pattern| (value.clone(), pattern)))
+                .collect::<Result<Vec<_>, String>>()
+        };
+        Ok(PipelineFilter {
+            skip: parse(skip)?,
+            only: parse(only)?,
+            mode,
+        })
+    }
+
+    /// Removes the filtered out pipelines from `scope` and returns why each of them was removed.
+    /// `--only` keeps the dependents of the pipelines it leaves out, `--skip` drops them too
+    /// in the subtree mode.
+    pub fn apply(
+        &self,
+        graph: &DependencyGraph,
+        scope: &mut BTreeSet<String>,
+    ) -> BTreeMap<String, String> {
+        let mut removed: BTreeMap<String, String> = BTreeMap::new();
+        let mut skipped = vec![];
+        for slug in scope.iter() {
+            let node = match graph.get(slug) {
+                Some(node) => node,
+                None => continue,
+            };
+            if let Some((value, _)) = self.skip.iter().find(|(_, p)| p.matches(node)) {
+                removed.insert(slug.clone(), format!("matches --skip {}", value));
+                skipped.push(slug.clone());
+            } else if !self.only.is_empty() && !self.only.iter().any(|(_, p)| p.matches(node)) {
+                removed.insert(slug.clone(), "not matched by --only".to_string());
+            }
+        }
+
+        if self.mode == SkipMode::Subtree {
+            for root in &skipped {
+                for slug in graph.downstream_of(root) {
+                    if scope.contains(&slug) && !removed.contains_key(&slug) {
+                        removed.insert(slug, format!("downstream of the skipped {}", root));
+                    }
+                }
+            }
+        }
+
+        scope.retain(|slug| !removed.contains_key(slug));
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(slug: &str, kind: NodeKind, dependencies: &[&str]) -> Node {
+        Node {
+            slug: slug.to_string(),
+            kind,
+            pipeline: None,
+            dependencies: dependencies.iter().map(|dep| dep.to_string()).collect(),
+            status: None,
+        }
+    }
+
+    #[test]
+    fn star_matches_any_run_including_an_empty_one() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "IAMService"));
+        assert!(glob_match("*Portal", "Portal"));
+        assert!(glob_match("*Portal", "AdminPortal"));
+        assert!(glob_match("IAM*", "IAM"));
+        assert!(glob_match("I*S*e", "IAMService"));
+        assert!(glob_match("**", "IAMService"));
+        assert!(!glob_match("*Portal", "PortalService"));
+        assert!(!glob_match("IAM*", "MyIAMService"));
+    }
+
+    #[test]
+    fn question_mark_matches_exactly_one_character() {
+        assert!(glob_match("?", "a"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("?", "ab"));
+        assert!(glob_match("Service?", "ServiceA"));
+        assert!(!glob_match("Service?", "Service"));
+        assert!(glob_match("*?", "a"));
+        assert!(!glob_match("*?", ""));
+        assert!(glob_match("?é?", "aéb"));
+    }
+
+    #[test]
+    fn globs_without_wildcards_match_exactly() {
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "a"));
+        assert!(glob_match(
+            "@gingersociety/IAMService",
+            "@gingersociety/IAMService"
+        ));
+        assert!(!glob_match("iamservice", "IAMService"));
+    }
+
+    #[test]
+    fn unknown_kinds_are_rejected() {
+        assert!(PipelineFilter::new(&["kind:lambda".to_string()], &[], SkipMode::Node).is_err());
+        assert!(PipelineFilter::new(&["kind:queue".to_string()], &[], SkipMode::Node).is_ok());
+    }
+
+    #[test]
+    fn subtree_mode_also_skips_the_dependents() {
+        let mut graph = DependencyGraph::new();
+        graph.add_node(node("@o/schema", NodeKind::DbSchema, &[]));
+        graph.add_node(node("@o/Orders", NodeKind::Service, &["@o/schema"]));
+        graph.add_node(node("@o/Shop", NodeKind::Portal, &["@o/Orders"]));
+        graph.add_node(node("@o/IAM", NodeKind::Service, &[]));
+        let all: BTreeSet<String> = graph.nodes().map(|node| node.slug.clone()).collect();
+
+        let skip = ["kind:db".to_string()];
+        let mut scope = all.clone();
+        PipelineFilter::new(&skip, &[], SkipMode::Node)
+            .unwrap()
+            .apply(&graph, &mut scope);
+        assert_eq!(
+            scope,
+            BTreeSet::from(["@o/IAM", "@o/Orders", "@o/Shop"].map(String::from))
+        );
+
+        let mut scope = all.clone();
+        let removed = PipelineFilter::new(&skip, &[], SkipMode::Subtree)
+            .unwrap()
+            .apply(&graph, &mut scope);
+        assert_eq!(scope, BTreeSet::from(["@o/IAM".to_string()]));
+        assert_eq!(removed["@o/Shop"], "downstream of the skipped @o/schema");
+    }
+
+    #[test]
+    fn only_matches_the_name_or_the_slug() {
+        let mut graph = DependencyGraph::new();
+        graph.add_node(node("@o/AdminPortal", NodeKind::Portal, &[]));
+        graph.add_node(node("@o/IAMService", NodeKind::Service, &[]));
+        let mut scope: BTreeSet<String> = graph.nodes().map(|node| node.slug.clone()).collect();
+
+        let only = ["*Portal".to_string(), "@o/IAM*".to_string()];
+        let removed = PipelineFilter::new(&[], &only, SkipMode::Node)
+            .unwrap()
+            .apply(&graph, &mut scope);
+        assert!(removed.is_empty());
+        assert_eq!(scope.len(), 2);
+    }
+}
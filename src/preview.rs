@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use MetadataService::apis::{
+    configuration::Configuration as MetadataConfiguration,
+    default_api::{metadata_get_services_and_envs, MetadataGetServicesAndEnvsParams},
+};
+
+use crate::{
+    environments::EnvKey,
+    pagination::{collect_pages, PAGE_SIZE},
+};
+
+pub struct PreviewDeployment {
+    pub base_url: String,
+    pub base_url_ws: Option<String>,
+}
+
+/// Services of the org that are deployed to the preview env, keyed by `@org/identifier`.
+/// Envs registered with an empty base url are not deployments and are skipped.
+pub async fn fetch_preview_deployments(
+    metadata_config: &MetadataConfiguration,
+    org_id: &str,
+    preview_env: &EnvKey,
+) -> HashMap<String, PreviewDeployment> {
+    let mut deployments = HashMap::new();
+    match collect_pages(|page| {
+        metadata_get_services_and_envs(
+            metadata_config,
+            MetadataGetServicesAndEnvsParams {
+                page_number: Some(page.to_string()),
+                page_size: Some(PAGE_SIZE.to_string()),
+                org_id: org_id.to_string(),
+            },
+        )
+    })
+    .await
+    {
+        Ok(services) => {
+            for service in services {
+                if let Some(env) = service
+                    .envs
+                    .iter()
+                    .find(|env| env.env_key == preview_env.as_str() && !env.base_url.is_empty())
+                {
+                    deployments.insert(
+                        format!("@{}/{}", service.organization_id, service.identifier),
+                        PreviewDeployment {
+                            base_url: env.base_url.clone(),
+                            base_url_ws: env
+                                .base_url_ws
+                                .clone()
+                                .flatten()
+                                .filter(|url| !url.is_empty()),
+                        },
+                    );
+                }
+            }
+        }
+        Err(e) => {
+            println!("{:?}", e);
+            println!("Unable to look up the preview deployments, connecting to the given env");
+        }
+    }
+    deployments
+}
@@ -1,12 +1,22 @@
-use crate::Environment;
+use crate::{
+    asyncapi::attach_asyncapi,
+    contract::{bundle_contract, read_contract_kind, ContractKind},
+    environments::{EnvKey, Environments},
+    pipeline_provider::quick_links_with_pipeline,
+    services_toml::read_committed_config,
+    spec_diff::{diff_specs, major_version},
+    spec_lint::{lint_spec, LintConfig, Severity},
+    usage::{attach_usage, collect_usage},
+};
 use colored::Colorize;
 use ginger_shared_rs::{
     read_consumer_db_config, read_package_metadata_file, read_releaser_config_file,
-    read_service_config_file, utils::get_package_json_info, LANG,
+    utils::get_package_json_info, LANG,
 };
 use reqwest::Client;
 use serde_json::Value as JsonValue;
 use std::{
+    collections::HashMap,
     fs::{self, File},
     io::Read,
     path::Path,
@@ -17,7 +27,10 @@ use toml::Value;
 use MetadataService::{
     apis::{
         configuration::Configuration as MetadataConfiguration,
-        default_api::{metadata_update_or_create_service, MetadataUpdateOrCreateServiceParams},
+        default_api::{
+            metadata_get_service_and_env_by_id, metadata_update_or_create_service,
+            MetadataGetServiceAndEnvByIdParams, MetadataUpdateOrCreateServiceParams,
+        },
     },
     models::UpdateServiceRequest,
 };
@@ -126,6 +139,36 @@ pub fn get_pyproject_toml_info() -> Option<(String, String, String, String, Vec<
 
     Some((name, version, description, organization, dependencies))
 }
+
+/// Name, version, description, organization and internal dependencies of the project, from
+/// the manifest of its language
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub organization: String,
+    pub dependencies: Vec<String>,
+}
+
+pub fn read_package_info(lang: &LANG) -> Result<PackageInfo, String> {
+    let (info, manifest) = match lang {
+        LANG::TS => (get_package_json_info(), "package.json"),
+        LANG::Rust => (get_cargo_toml_info(), "Cargo.toml"),
+        LANG::Python => (get_pyproject_toml_info(), "pyproject.toml"),
+        LANG::Shell => {
+            return Err("Shell projects have no manifest to read the name and version from".into())
+        }
+    };
+    let (name, version, description, organization, dependencies) =
+        info.ok_or_else(|| format!("Failed to get name and version from {}", manifest))?;
+    Ok(PackageInfo {
+        name,
+        version,
+        description,
+        organization,
+        dependencies,
+    })
+}
 async fn fetch_swagger_spec(
     client: &Client,
     url: &str,
@@ -164,18 +207,138 @@ async fn fetch_swagger_spec(
     None
 }
 
+/// Runs the spec linter and aborts the publish when any rule configured as an error fails
+fn lint_before_publish(config_path: &Path, spec: &str) {
+    let spec_json: JsonValue = match serde_json::from_str(spec) {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!(
+                "The spec is not valid JSON: {:?}. Aborting metadata publishing.",
+                e
+            );
+            exit(1);
+        }
+    };
+
+    let findings = lint_spec(&spec_json, &LintConfig::from_services_toml(config_path));
+    let mut errors = 0;
+    for finding in &findings {
+        match finding.severity {
+            Severity::Error => {
+                errors += 1;
+                eprintln!("{}", finding.to_string().red());
+            }
+            _ => println!("{}", finding.to_string().yellow()),
+        }
+    }
+
+    if errors > 0 {
+        eprintln!(
+            "The spec has {} lint error(s). Fix them or relax their severity in the [spec_lint] section of services.toml. Aborting metadata publishing.",
+            errors
+        );
+        exit(1);
+    }
+}
+
+/// Diffs the new spec against the one currently published for this service and env.
+/// Breaking changes abort the publish unless the major version in releaser.toml was
+/// bumped or `allow_breaking` is set.
+async fn check_breaking_changes(
+    metadata_config: &MetadataConfiguration,
+    organization: &str,
+    name: &str,
+    env: &EnvKey,
+    spec: &str,
+    releaser_major: u64,
+    allow_breaking: bool,
+) {
+    let published = match metadata_get_service_and_env_by_id(
+        metadata_config,
+        MetadataGetServiceAndEnvByIdParams {
+            service_identifier: name.to_string(),
+            env: env.to_string(),
+            org_id: organization.to_string(),
+        },
+    )
+    .await
+    {
+        Ok(response) => response.spec,
+        Err(_) => {
+            println!(
+                "No published spec found for {} in {}, skipping breaking change detection",
+                name, env
+            );
+            return;
+        }
+    };
+
+    let (old_spec, new_spec) = match (
+        serde_json::from_str::<JsonValue>(&published),
+        serde_json::from_str::<JsonValue>(spec),
+    ) {
+        (Ok(old_spec), Ok(new_spec)) => (old_spec, new_spec),
+        _ => {
+            println!("Unable to parse the specs, skipping breaking change detection");
+            return;
+        }
+    };
+
+    let changes = diff_specs(&old_spec, &new_spec);
+    if changes.is_empty() {
+        println!("No breaking changes detected against the published spec");
+        return;
+    }
+
+    println!(
+        "{} breaking change(s) detected against the published spec:",
+        changes.len()
+    );
+    for change in &changes {
+        println!("  {}", change.to_string().yellow());
+    }
+
+    let published_major = old_spec
+        .get("info")
+        .and_then(|info| info.get("version"))
+        .and_then(|v| v.as_str())
+        .and_then(major_version);
+
+    let major_bumped = match published_major {
+        Some(published_major) => releaser_major > published_major,
+        None => false,
+    };
+
+    if major_bumped {
+        println!("Major version was bumped, continuing with the publish");
+    } else if allow_breaking {
+        println!(
+            "{}",
+            "Publishing breaking changes without a major version bump (--allow-breaking)".red()
+        );
+    } else {
+        eprintln!(
+            "Breaking changes require a major version bump in releaser.toml. Use {} to publish anyway. Aborting metadata publishing.",
+            "--allow-breaking".blue()
+        );
+        exit(1);
+    }
+}
+
 pub async fn publish_metadata(
     config_path: &Path,
-    env: Environment,
+    env: EnvKey,
     metadata_config: &MetadataConfiguration,
     releaser_path: &Path,
     package_path: &Path,
+    allow_breaking: bool,
+    with_usage: bool,
 ) {
     let package_metadata = read_package_metadata_file(package_path).unwrap();
 
-    let links_str = serde_json::to_string(&package_metadata.links).unwrap();
+    let links_str = quick_links_with_pipeline(&package_metadata.links, package_path);
 
-    let services_config = match read_service_config_file(config_path) {
+    let services_config = match read_committed_config(config_path) {
         Ok(c) => c,
         Err(e) => {
             println!("{:?}", e);
@@ -196,24 +359,19 @@ pub async fn publish_metadata(
         }
     };
 
-    let (mut name, version, description, organization, dependencies) = match services_config.lang {
-        LANG::TS => get_package_json_info().unwrap_or_else(|| {
-            eprintln!("Failed to get name and version from package.json");
-            exit(1);
-        }),
-        LANG::Rust => get_cargo_toml_info().unwrap_or_else(|| {
-            eprintln!("Failed to get name and version from Cargo.toml");
-            exit(1);
-        }),
-        LANG::Python => get_pyproject_toml_info().unwrap_or_else(|| {
-            eprintln!("Failed to get name and version from pyproject.toml");
-            exit(1);
-        }),
-        LANG::Shell => todo!(),
-    };
+    let PackageInfo {
+        mut name,
+        version,
+        description,
+        organization,
+        dependencies,
+    } = read_package_info(&services_config.lang).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
 
     if services_config.override_name.is_some() {
-        name = services_config.override_name.unwrap()
+        name = services_config.override_name.clone().unwrap()
     }
 
     println!("Package name: {}", name);
@@ -223,57 +381,50 @@ pub async fn publish_metadata(
     println!("git: {:?}", releaser_config.settings.git_url_prefix);
 
     let client = Client::new();
-    let env_base_url_swagger = match env {
-        Environment::Dev => &services_config.urls.clone().unwrap()["dev"],
-        Environment::Stage => &services_config.urls.clone().unwrap()["stage"],
-        Environment::Prod => &services_config.urls.clone().unwrap()["prod"],
-        Environment::ProdK8 => &services_config.urls.clone().unwrap()["prod"],
-        Environment::StageK8 => &services_config.urls.clone().unwrap()["stage"],
-    };
+    let environments = Environments::load(config_path);
+    if let Err(e) = environments.ensure_known(&env) {
+        eprintln!("{}", e);
+        exit(1);
+    }
 
-    let env_base_url = match env {
-        Environment::Dev => &services_config.urls.clone().unwrap()["dev"],
-        Environment::Stage => &services_config.urls.clone().unwrap()["stage"],
-        Environment::Prod => &services_config.urls.clone().unwrap()["prod"],
-        Environment::ProdK8 => &services_config.urls.clone().unwrap()["prod_k8"],
-        Environment::StageK8 => &services_config.urls.clone().unwrap()["stage_k8"],
+    let urls = services_config.urls.clone().unwrap_or_default();
+    // Previews never fall back to the url of another env
+    let resolve_url = |values: &HashMap<String, String>, env: &EnvKey| match env.preview_id() {
+        Some(_) => environments.resolve_preview(values, env),
+        None => environments
+            .resolve(values, env)
+            .map(|url| env.expand_url(url)),
     };
 
-    let env_base_url_ws = match env {
-        Environment::Dev => services_config.urls_ws.clone().unwrap().get("dev").cloned(),
-        Environment::Stage => services_config
-            .urls_ws
-            .clone()
-            .unwrap()
-            .get("stage")
-            .cloned(),
-        Environment::Prod => services_config
-            .urls_ws
-            .clone()
-            .unwrap()
-            .get("prod")
-            .cloned(),
-        Environment::ProdK8 => services_config
-            .urls_ws
-            .clone()
-            .unwrap()
-            .get("prod_k8")
-            .cloned(),
-        Environment::StageK8 => services_config
-            .urls_ws
-            .clone()
-            .unwrap()
-            .get("stage_k8")
-            .cloned(),
-    }
-    .unwrap_or(String::from("")); // Default to None if the key is not found
+    let env_base_url_swagger = resolve_url(&urls, &env.spec_source()).unwrap_or_else(|e| {
+        eprintln!(
+            "Unable to find the url to fetch the spec from in services.toml. {}",
+            e
+        );
+        exit(1);
+    });
+
+    let env_base_url = resolve_url(&urls, &env).unwrap_or_else(|e| {
+        eprintln!("Unable to find the base url in services.toml. {}", e);
+        exit(1);
+    });
+
+    let env_base_url_ws = services_config
+        .urls_ws
+        .as_ref()
+        .and_then(|urls_ws| resolve_url(urls_ws, &env).ok())
+        .unwrap_or(String::from("")); // Default to None if the key is not found
 
     println!("env_base_url_ws: {:?} , ", env_base_url_ws);
     let spec_url = services_config.spec_url.clone();
-    let spec = if let Some(spec_url) = spec_url {
+    let contract_kind = read_contract_kind(config_path);
+    let spec = if contract_kind != ContractKind::OpenApi {
+        println!("Bundling the {} contract", contract_kind);
+        bundle_contract(config_path, contract_kind)
+    } else if let Some(spec_url) = spec_url {
         let full_url = format!("{}{}", env_base_url_swagger, spec_url);
 
-        if env != Environment::Dev {
+        if !env.is_dev() {
             tokio::time::sleep(Duration::from_secs(10)).await;
         }
 
@@ -285,6 +436,37 @@ pub async fn publish_metadata(
         String::new()
     };
 
+    if contract_kind == ContractKind::OpenApi && !spec.is_empty() {
+        lint_before_publish(config_path, &spec);
+
+        let releaser_major = releaser_config
+            .version
+            .major
+            .to_string()
+            .parse::<u64>()
+            .unwrap_or(0);
+        check_breaking_changes(
+            metadata_config,
+            &organization,
+            &name,
+            &env,
+            &spec,
+            releaser_major,
+            allow_breaking,
+        )
+        .await;
+    }
+
+    let spec = if with_usage {
+        let usage = collect_usage(&services_config);
+        println!("Publishing usage: {:?}", usage);
+        attach_usage(&spec, &usage)
+    } else {
+        spec
+    };
+
+    let spec = attach_asyncapi(config_path, &spec);
+
     let db_config_path = Path::new("database.toml");
     let (tables, schema_id, cache_schema_id, message_queue_schema_id) =
         match read_consumer_db_config(db_config_path) {
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fs::{self, read_to_string, write, File, OpenOptions},
     io::{self, Write},
     path::{Path, PathBuf},
@@ -6,13 +7,23 @@ use std::{
 };
 
 use colored::Colorize;
-use ginger_shared_rs::{read_service_config_file, Service, LANG};
+use ginger_shared_rs::{Service, LANG};
 use MetadataService::apis::configuration::Configuration as MetadataConfiguration;
 use MetadataService::apis::default_api::{
     metadata_get_service_and_env_by_id, MetadataGetServiceAndEnvByIdParams,
 };
 
-use crate::{file_utils::replace_in_file, Environment};
+use crate::{
+    asyncapi::{extract_asyncapi, generate_ws_client},
+    base_urls::BaseUrls,
+    contract::{has_openapi_paths, published_contract_kind, ContractKind},
+    environments::{EnvKey, Environments},
+    file_utils::{add_to_gitignore, replace_in_file},
+    graphql::generate_graphql_client,
+    grpc::generate_grpc_client,
+    preview::fetch_preview_deployments,
+    services_toml::read_services_config,
+};
 
 fn replace_in_files_recursive(dir_path: &str, pattern: &str, replacement: &str) -> io::Result<()> {
     for entry in fs::read_dir(dir_path)? {
@@ -29,7 +40,7 @@ fn replace_in_files_recursive(dir_path: &str, pattern: &str, replacement: &str)
     }
     Ok(())
 }
-fn open_api_client_generator(service: &Service, lang: LANG, root_dir: &str, base_url: &str) {
+fn open_api_client_generator(service: &Service, lang: LANG, root_dir: &str, base_urls: &BaseUrls) {
     let output_dir = format!("{}/{}_client", root_dir, service.name);
     println!("Generating client for: {:?}", service);
 
@@ -91,16 +102,16 @@ fn open_api_client_generator(service: &Service, lang: LANG, root_dir: &str, base
                         lib_rs_content = format!(
                             r#"
     use apis::configuration::Configuration;
-    
+    {base_urls}
     pub fn get_configuration() -> Configuration {{
         let config = Configuration {{
-            base_path: "{url}".to_string(),
+            base_path: base_url(),
             ..Default::default()
         }};
         config
     }}
     "#,
-                            url = base_url
+                            base_urls = base_urls.render_rust(&service.name)
                         );
                     } else {
                         lib_rs_content = format!(
@@ -109,7 +120,7 @@ fn open_api_client_generator(service: &Service, lang: LANG, root_dir: &str, base
     use std::{{env, process::exit}};
     
     use apis::configuration::{{ApiKey, Configuration}};
-    
+    {base_urls}
     pub fn get_configuration(token_arg: Option<String>) -> Configuration {{
         let token = match token_arg {{
             Some(t) => t,
@@ -120,7 +131,7 @@ fn open_api_client_generator(service: &Service, lang: LANG, root_dir: &str, base
             }}),
         }};
         let config = Configuration {{
-            base_path: "{url}".to_string(),
+            base_path: base_url(),
             api_key: Some(ApiKey {{
                 key: token,
                 prefix: Some("".to_string()),
@@ -131,13 +142,13 @@ fn open_api_client_generator(service: &Service, lang: LANG, root_dir: &str, base
     }}
     pub fn get_configuration_without_auth() -> Configuration {{
         let config = Configuration {{
-            base_path: "{url}".to_string(),
+            base_path: base_url(),
             ..Default::default()
         }};
         config
     }}
     "#,
-                            url = base_url
+                            base_urls = base_urls.render_rust(&service.name)
                         );
                     }
 
@@ -254,11 +265,14 @@ import {{ Configuration }} from './runtime'
 export * from './runtime';
 export * from './apis/index';
 export * from './models/index';
+
+{base_urls}
 const getToken = (): string | null => {{
     return localStorage.getItem('access_token'); // Adjust the key name as needed
 }};
+// Requests are prefixed with the base url when they are sent so that it can change at runtime
 const configuration = new Configuration({{
-    basePath: '{}',
+    basePath: '',
     middleware: [
         {{
             pre: async (context) => {{
@@ -269,7 +283,7 @@ const configuration = new Configuration({{
                         Authorization: token,
                     }};
                 }}
-                return Promise.resolve(context);
+                return Promise.resolve({{ url: getBaseUrl() + context.url, init: context.init }});
             }},
         }},
     ],
@@ -277,7 +291,7 @@ const configuration = new Configuration({{
 const client = new DefaultApi(configuration)
 export default client
 "#,
-                        base_url
+                        base_urls = base_urls.render_ts(&service.name)
                     );
 
                     match OpenOptions::new()
@@ -380,17 +394,18 @@ from IAMService_client.IAMService import (
     Configuration,
 )
 
+{base_urls}
 
 def get_configuration(access_token):
 
     if access_token is None:
         return Configuration(
-            host="{url}",
+            host=get_base_url(),
             ssl_ca_cert=certifi.where(),
         )
     else:
         return Configuration(
-            host="{url}",
+            host=get_base_url(),
             ssl_ca_cert=certifi.where(),
             api_key={{
                 "BearerAuth": access_token
@@ -403,12 +418,12 @@ def get_api_configuration(access_token):
 
     if access_token is None:
         return Configuration(
-            host="{url}",
+            host=get_base_url(),
             ssl_ca_cert=certifi.where(),
         )
     else:
         return Configuration(
-            host="{url}",
+            host=get_base_url(),
             ssl_ca_cert=certifi.where(),
             api_key={{
                 "BearerAPIAuth": access_token
@@ -416,7 +431,7 @@ def get_api_configuration(access_token):
             api_key_prefix={{"BearerAPIAuth": "Bearer"}},
         )
                         "#,
-                        url = base_url
+                        base_urls = base_urls.render_python(&service.name)
                     );
 
                     match OpenOptions::new()
@@ -449,8 +464,8 @@ fn print_openapi_generator_not_found() {
     exit(1);
 }
 
-pub fn generate_references(config_path: &Path, env: Environment) {
-    let services_config = match read_service_config_file(config_path) {
+pub fn generate_references(config_path: &Path, env: EnvKey) {
+    let services_config = match read_services_config(config_path) {
         Ok(c) => c,
         Err(err) => {
             println!("{:?}", err);
@@ -462,6 +477,12 @@ pub fn generate_references(config_path: &Path, env: Environment) {
         }
     };
 
+    let environments = Environments::load(config_path);
+    if let Err(e) = environments.ensure_known(&env) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+
     // Process services and generate references content
     let mut references_content = match services_config.lang {
         LANG::TS => {
@@ -486,7 +507,7 @@ pub fn generate_references(config_path: &Path, env: Environment) {
     // Process portals_refs if available
     if let Some(portals_refs) = &services_config.portals_refs {
         for (portal_name, portal_envs) in portals_refs {
-            if let Some(portal_url) = portal_envs.get(&env.to_string()) {
+            if let Ok(portal_url) = environments.resolve(portal_envs, &env) {
                 let formatted_name = portal_name
                     .replace("-", "_")
                     .replace("@", "")
@@ -512,7 +533,7 @@ pub fn generate_references(config_path: &Path, env: Environment) {
 
     if let Some(ws_ref) = &services_config.ws_refs {
         for (ws_name, ws_envs) in ws_ref {
-            if let Some(ws_url) = ws_envs.get(&env.to_string()) {
+            if let Ok(ws_url) = environments.resolve(ws_envs, &env) {
                 let formatted_name = ws_name
                     .replace("-", "_")
                     .replace("@", "")
@@ -553,25 +574,8 @@ pub fn generate_references(config_path: &Path, env: Environment) {
     println!("References generated successfully in '{}'", refs_file);
 
     // Add portal_refs_file to .gitignore if it's not already present
-    let gitignore_path = Path::new(".gitignore");
-    let portal_refs_file_str = refs_file.as_str();
-
-    if gitignore_path.exists() {
-        let gitignore_content = fs::read_to_string(gitignore_path).unwrap();
-        if !gitignore_content.contains(portal_refs_file_str) {
-            let mut gitignore_file = OpenOptions::new()
-                .append(true)
-                .open(gitignore_path)
-                .unwrap();
-            writeln!(gitignore_file, "\n{}", portal_refs_file_str).unwrap();
-            println!("Added '{}' to .gitignore", portal_refs_file_str);
-        } else {
-            println!("'{}' is already in .gitignore", portal_refs_file_str);
-        }
-    } else {
-        let mut gitignore_file = File::create(gitignore_path).unwrap();
-        writeln!(gitignore_file, "{}", portal_refs_file_str).unwrap();
-        println!("Created .gitignore and added '{}'", portal_refs_file_str);
+    if let Err(err) = add_to_gitignore(refs_file) {
+        println!("Failed to update .gitignore: {:?}", err);
     }
 }
 
@@ -591,12 +595,50 @@ fn extract_org_and_package(input: &str) -> Option<(String, String)> {
     None
 }
 
+/// Fetches the published spec of a dependency for the env, following its fallback chain
+async fn fetch_published_spec(
+    metadata_config: &MetadataConfiguration,
+    environments: &Environments,
+    org_id: &str,
+    package_name: &str,
+    env: &EnvKey,
+) -> Result<String, String> {
+    let mut errors = vec![];
+    for env_name in environments.chain(env) {
+        match metadata_get_service_and_env_by_id(
+            metadata_config,
+            MetadataGetServiceAndEnvByIdParams {
+                service_identifier: package_name.to_string(),
+                env: env_name.clone(),
+                org_id: org_id.to_string(),
+            },
+        )
+        .await
+        {
+            Ok(response) => {
+                if env_name != env.as_str() {
+                    println!(
+                        "{} is not published in {}, using the spec from {}",
+                        package_name, env, env_name
+                    );
+                }
+                return Ok(response.spec);
+            }
+            Err(e) => errors.push(format!("{}: {:?}", env_name, e)),
+        }
+    }
+    Err(errors.join("\n"))
+}
+
+/// Generates the clients of the dependencies. With a preview id, dependencies that have a
+/// preview deployment for it are connected to that deployment and the others to `env`.
 pub async fn generate_client(
     config_path: &Path,
-    env: Environment,
+    env: EnvKey,
+    preview: Option<String>,
     metadata_config: &MetadataConfiguration,
 ) {
-    let services_config = match read_service_config_file(config_path) {
+    let services_config = match read_services_config(config_path) {
         Ok(c) => c,
         Err(err) => {
             println!("{:?}", err);
@@ -619,33 +661,68 @@ pub async fn generate_client(
         }
     }
 
-    for (service_name, service_urls) in services_config.services.unwrap().iter() {
-        let base_url = match env {
-            Environment::Dev => service_urls["dev"].clone(),
-            Environment::Stage => service_urls["stage"].clone(),
-            Environment::Prod => service_urls["prod"].clone(),
-            Environment::ProdK8 => service_urls["prod_k8"].clone(),
-            Environment::StageK8 => service_urls["stage_k8"].clone(),
+    let environments = Environments::load(config_path);
+    if let Err(e) = environments.ensure_known(&env) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+
+    let preview_env = preview.as_deref().map(EnvKey::preview);
+    let mut preview_deployments = HashMap::new();
+
+    for (service_name, service_urls) in services_config.services.clone().unwrap().iter() {
+        let preview_deployment = match &preview_env {
+            Some(preview_env) => match extract_org_and_package(service_name) {
+                Some((org_id, _)) => {
+                    if !preview_deployments.contains_key(&org_id) {
+                        let deployments =
+                            fetch_preview_deployments(metadata_config, &org_id, preview_env).await;
+                        preview_deployments.insert(org_id.clone(), deployments);
+                    }
+                    preview_deployments[&org_id].get(service_name)
+                }
+                None => None,
+            },
+            None => None,
+        };
+
+        // Dependencies without a preview deployment are connected to the given env
+        let dependency_env = match preview_deployment {
+            Some(_) => preview_env.clone().unwrap(),
+            None => env.clone(),
+        };
+        if preview_env.is_some() {
+            println!("{} : connecting to {}", service_name, dependency_env);
+        }
+
+        let base_url = match preview_deployment {
+            Some(deployment) => deployment.base_url.clone(),
+            None => match environments.resolve(service_urls, &dependency_env) {
+                Ok(url) => url.clone(),
+                Err(e) => {
+                    eprintln!("{} : {}", service_name, e);
+                    exit(1);
+                }
+            },
         };
 
         if let Some((org_id, package_name)) = extract_org_and_package(service_name) {
             println!("org_id: {}, package_name: {}", org_id, package_name);
-            match metadata_get_service_and_env_by_id(
+            let spec = match fetch_published_spec(
                 metadata_config,
-                MetadataGetServiceAndEnvByIdParams {
-                    service_identifier: package_name.clone(),
-                    env: env.to_string(),
-                    org_id: org_id.clone(),
-                },
+                &environments,
+                &org_id,
+                &package_name,
+                &dependency_env,
             )
             .await
             {
-                Ok(response) => {
+                Ok(spec) => {
                     let spec_path = ginger_tmp_dir.join(format!(
                         "{}@{}.{}.spec.json",
                         package_name.clone(),
                         org_id.clone(),
-                        env
+                        dependency_env
                     ));
                     match OpenOptions::new()
                         .write(true)
@@ -654,30 +731,102 @@ pub async fn generate_client(
                         .open(&spec_path)
                     {
                         Ok(mut file) => {
-                            if let Err(e) = file.write_all(response.spec.as_bytes()) {
+                            if let Err(e) = file.write_all(spec.as_bytes()) {
                                 eprintln!("Error writing to {}: {:?}", spec_path.display(), e);
                             }
                         }
                         Err(e) => eprintln!("Error creating {}: {:?}", spec_path.display(), e),
                     }
+                    Some(spec)
                 }
                 Err(e) => {
-                    println!("{:?}", e)
+                    println!("{}", e);
+                    None
+                }
+            };
+
+            let contract_kind = spec
+                .as_deref()
+                .map(published_contract_kind)
+                .unwrap_or(ContractKind::OpenApi);
+            let base_urls =
+                BaseUrls::collect(&environments, service_urls, &dependency_env, &base_url);
+
+            let without_paths = matches!(spec.as_deref(), Some(spec) if !has_openapi_paths(spec));
+
+            match contract_kind {
+                ContractKind::Grpc => {
+                    let bundle: serde_json::Value =
+                        serde_json::from_str(spec.as_deref().unwrap_or("{}")).unwrap();
+                    generate_grpc_client(
+                        &bundle,
+                        &package_name,
+                        services_config.lang,
+                        &services_config.dir.clone().unwrap(),
+                        &base_urls,
+                    );
                 }
+                ContractKind::GraphQL => {
+                    let bundle: serde_json::Value =
+                        serde_json::from_str(spec.as_deref().unwrap_or("{}")).unwrap();
+                    generate_graphql_client(
+                        &bundle,
+                        &package_name,
+                        services_config.lang,
+                        &services_config.dir.clone().unwrap(),
+                        &base_urls,
+                    );
+                }
+                ContractKind::OpenApi if without_paths => {
+                    println!(
+                        "{} publishes no OpenAPI paths, skipping its OpenAPI client",
+                        service_name
+                    );
+                }
+                ContractKind::OpenApi => open_api_client_generator(
+                    &Service {
+                        schema_url: format!(
+                            ".ginger.tmp/{}@{}.{}.spec.json",
+                            package_name, org_id, dependency_env
+                        ),
+                        name: package_name.to_string(),
+                    },
+                    services_config.lang,
+                    &services_config.dir.clone().unwrap(),
+                    &base_urls,
+                ),
             }
 
-            open_api_client_generator(
-                &Service {
-                    schema_url: format!(
-                        ".ginger.tmp/{}@{}.{}.spec.json",
-                        package_name, org_id, env
-                    ),
-                    name: package_name.to_string(),
-                },
-                services_config.lang,
-                &services_config.dir.clone().unwrap(),
-                &base_url,
-            );
+            if let Some(document) = spec.as_deref().and_then(extract_asyncapi) {
+                let ws_envs = services_config
+                    .ws_refs
+                    .as_ref()
+                    .and_then(|refs| refs.get(service_name))
+                    .cloned()
+                    .unwrap_or_default();
+                let ws_url = match preview_deployment {
+                    Some(deployment) => deployment.base_url_ws.clone().unwrap_or_default(),
+                    None => environments
+                        .resolve(&ws_envs, &dependency_env)
+                        .cloned()
+                        .unwrap_or_default(),
+                };
+                if ws_url.is_empty() {
+                    println!(
+                        "No websocket url found for {} in {}, run {} to refresh it",
+                        service_name,
+                        dependency_env,
+                        "ginger-connector config".blue()
+                    );
+                }
+                generate_ws_client(
+                    &document,
+                    &package_name,
+                    services_config.lang,
+                    &services_config.dir.clone().unwrap(),
+                    &BaseUrls::collect(&environments, &ws_envs, &dependency_env, &ws_url),
+                );
+            }
         } else {
             println!("Input is not in the expected format");
         }
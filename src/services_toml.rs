@@ -0,0 +1,344 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use ginger_shared_rs::{write_service_config_file, ServiceConfig};
+use toml::Value;
+
+/// Sections of services.toml owned by this tool rather than by `ServiceConfig`
+const EXTENSION_SECTIONS: [&str; 4] = ["spec_lint", "asyncapi", "contract", "environments"];
+
+/// Per env url maps in which `${VAR}` / `${VAR:-default}` are interpolated
+const INTERPOLATED_SECTIONS: [&str; 5] = ["services", "urls", "urls_ws", "portals_refs", "ws_refs"];
+
+/// Git ignored overlay merged over services.toml at read time
+pub const LOCAL_OVERLAY_FILE: &str = "services.local.toml";
+
+fn read_toml(path: &Path) -> Option<Value> {
+    let content = fs::read_to_string(path).ok()?;
+    toml::from_str(&content).ok()
+}
+
+fn local_overlay_path(config_path: &Path) -> PathBuf {
+    config_path.with_file_name(LOCAL_OVERLAY_FILE)
+}
+
+/// Tables are merged key by key, any other value in the overlay replaces the base one
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Expands `${VAR}` and `${VAR:-default}`, `$${` being a literal `${`. Unset variables
+/// without a default are an error rather than an empty url.
+pub fn interpolate(value: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            result.push_str(&rest[..start - 1]);
+            result.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| format!("Unterminated ${{ in {:?}", value))?;
+        let expression = &rest[start + 2..end];
+        let (name, default) = match expression.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expression, None),
+        };
+        match (env::var(name).ok().filter(|v| !v.is_empty()), default) {
+            (Some(v), _) => result.push_str(&v),
+            (None, Some(default)) => result.push_str(default),
+            (None, None) => {
+                return Err(format!(
+                    "{} is not set and {:?} has no default, use ${{{}:-<default>}}",
+                    name, value, name
+                ))
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn interpolate_values(value: &mut Value) -> Result<(), String> {
+    match value {
+        Value::String(s) => *s = interpolate(s)?,
+        Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                interpolate_values(value)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// services.toml, with services.local.toml merged over it when `with_overlay` is set
+fn read_merged_config(config_path: &Path, with_overlay: bool) -> Result<Value, String> {
+    let content = fs::read_to_string(config_path).map_err(|e| e.to_string())?;
+    let mut config: Value = toml::from_str(&content).map_err(|e| e.to_string())?;
+
+    let overlay_path = local_overlay_path(config_path);
+    if with_overlay && overlay_path.exists() {
+        let overlay = read_toml(&overlay_path)
+            .ok_or_else(|| format!("{} is not valid TOML", overlay_path.display()))?;
+        merge(&mut config, overlay);
+    }
+    Ok(config)
+}
+
+fn to_service_config(mut config: Value) -> Result<ServiceConfig, String> {
+    if let Value::Table(table) = &mut config {
+        for key in INTERPOLATED_SECTIONS {
+            if let Some(section) = table.get_mut(key) {
+                interpolate_values(section).map_err(|e| format!("[{}] {}", key, e))?;
+            }
+        }
+    }
+    config
+        .try_into()
+        .map_err(|e: toml::de::Error| e.to_string())
+}
+
+/// Reads the service config as the commands should see it: services.local.toml merged over
+/// services.toml and the url maps interpolated
+pub fn read_services_config(config_path: &Path) -> Result<ServiceConfig, String> {
+    to_service_config(read_merged_config(config_path, true)?)
+}
+
+/// Reads the service config without the local overlay, for the commands publishing it to
+/// Metadata so that local urls and secrets never leave the machine
+pub fn read_committed_config(config_path: &Path) -> Result<ServiceConfig, String> {
+    to_service_config(read_merged_config(config_path, false)?)
+}
+
+/// Reads a section of services.toml that is not part of `ServiceConfig`, overlay included.
+/// `None` when there is no services.toml or no such section.
+pub fn read_config_section(config_path: &Path, key: &str) -> Result<Option<Value>, String> {
+    if !config_path.exists() {
+        return Ok(None);
+    }
+    Ok(read_merged_config(config_path, true)?.get(key).cloned())
+}
+
+/// `read_config_section` for the callers that can't go on with an unreadable services.toml
+pub fn config_section_or_exit(config_path: &Path, key: &str) -> Option<Value> {
+    read_config_section(config_path, key).unwrap_or_else(|e| {
+        eprintln!("Unable to read [{}] from services.toml: {}", key, e);
+        exit(1);
+    })
+}
+
+/// Writes the service config while keeping the extension sections, which
+/// `write_service_config_file` would otherwise drop
+pub fn write_service_config_preserving(
+    config_path: &Path,
+    config: &ServiceConfig,
+) -> Result<(), String> {
+    // Read from the committed file only so that local overrides are not written back
+    let committed = read_toml(config_path);
+    let preserved: Vec<(&str, Value)> = EXTENSION_SECTIONS
+        .iter()
+        .filter_map(|key| {
+            committed
+                .as_ref()
+                .and_then(|config| config.get(*key).cloned())
+                .map(|value| (*key, value))
+        })
+        .collect();
+
+    write_service_config_file(config_path, config).map_err(|e| format!("{:?}", e))?;
+
+    if preserved.is_empty() {
+        return Ok(());
+    }
+
+    let written = fs::read_to_string(config_path).map_err(|e| e.to_string())?;
+    let mut merged = match toml::from_str::<Value>(&written).map_err(|e| e.to_string())? {
+        Value::Table(table) => table,
+        _ => return Ok(()),
+    };
+    for (key, value) in preserved {
+        merged.insert(key.to_string(), value);
+    }
+
+    let content = toml::to_string(&Value::Table(merged)).map_err(|e| e.to_string())?;
+    fs::write(config_path, content).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, process};
+
+    use ginger_shared_rs::read_service_config_file;
+
+    use super::*;
+
+    fn toml_value(content: &str) -> Value {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn variables_are_expanded_or_fall_back_to_their_default() {
+        env::set_var("SERVICES_TOML_TEST_IAM_URL", "https://iam.example.com");
+        env::set_var("SERVICES_TOML_TEST_EMPTY", "");
+        assert_eq!(
+            interpolate("${SERVICES_TOML_TEST_IAM_URL:-http://localhost:8080}/iam"),
+            Ok("https://iam.example.com/iam".to_string())
+        );
+        assert_eq!(
+            interpolate("${SERVICES_TOML_TEST_UNSET:-http://localhost:8080}/iam"),
+            Ok("http://localhost:8080/iam".to_string())
+        );
+        // An empty variable is as good as unset
+        assert_eq!(
+            interpolate("${SERVICES_TOML_TEST_EMPTY:-http://localhost:8080}"),
+            Ok("http://localhost:8080".to_string())
+        );
+        assert_eq!(
+            interpolate("http://localhost:8080/iam"),
+            Ok("http://localhost:8080/iam".to_string())
+        );
+    }
+
+    #[test]
+    fn unset_variables_without_default_are_an_error() {
+        let error = interpolate("${SERVICES_TOML_TEST_UNSET}/iam").unwrap_err();
+        assert!(error.starts_with("SERVICES_TOML_TEST_UNSET is not set"));
+        assert!(interpolate("${SERVICES_TOML_TEST_UNSET:-http://localhost").is_err());
+    }
+
+    #[test]
+    fn escaped_references_are_kept_literally() {
+        env::set_var("SERVICES_TOML_TEST_HOST", "localhost");
+        assert_eq!(
+            interpolate("http://$${SERVICES_TOML_TEST_HOST}/${SERVICES_TOML_TEST_HOST}"),
+            Ok("http://${SERVICES_TOML_TEST_HOST}/localhost".to_string())
+        );
+        assert_eq!(interpolate("a$b$$c"), Ok("a$b$$c".to_string()));
+    }
+
+    #[test]
+    fn overlays_merge_into_nested_tables() {
+        let mut base = toml_value(
+            r#"
+lang = "Rust"
+
+[services."@gingersociety/IAMService"]
+dev = "http://localhost:8080/iam"
+stage = "https://stage.example.com/iam"
+
+[spec_lint]
+inline_schema = "off"
+"#,
+        );
+        merge(
+            &mut base,
+            toml_value(
+                r#"
+lang = "TS"
+
+[services."@gingersociety/IAMService"]
+dev = "http://localhost:9001/iam"
+
+[services."@gingersociety/MetadataService"]
+dev = "http://localhost:8081/metadata"
+"#,
+            ),
+        );
+        assert_eq!(
+            base,
+            toml_value(
+                r#"
+lang = "TS"
+
+[services."@gingersociety/IAMService"]
+dev = "http://localhost:9001/iam"
+stage = "https://stage.example.com/iam"
+
+[services."@gingersociety/MetadataService"]
+dev = "http://localhost:8081/metadata"
+
+[spec_lint]
+inline_schema = "off"
+"#
+            )
+        );
+    }
+
+    #[test]
+    fn written_config_keeps_templates_and_leaves_the_overlay_out() {
+        let dir = env::temp_dir().join(format!("services_toml_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("services.toml");
+        fs::write(
+            &config_path,
+            r#"
+lang = "Rust"
+organization_id = "gingersociety"
+dir = "src/services"
+
+[services."@gingersociety/IAMService"]
+dev = "${SERVICES_TOML_TEST_IAM:-http://localhost:8080}/iam"
+
+[spec_lint]
+inline_schema = "off"
+"#,
+        )
+        .unwrap();
+        fs::write(
+            local_overlay_path(&config_path),
+            r#"
+[services."@gingersociety/IAMService"]
+stage = "http://localhost:9001/iam"
+"#,
+        )
+        .unwrap();
+        env::set_var("SERVICES_TOML_TEST_IAM", "http://iam.local");
+
+        let seen = read_services_config(&config_path).unwrap();
+        let iam = &seen.services.as_ref().unwrap()["@gingersociety/IAMService"];
+        assert_eq!(iam["dev"], "http://iam.local/iam");
+        assert_eq!(iam["stage"], "http://localhost:9001/iam");
+
+        // What `config` does: the committed file as is, with a dependency added
+        let mut config = read_service_config_file(&config_path).unwrap();
+        config.services.as_mut().unwrap().insert(
+            "@gingersociety/MetadataService".to_string(),
+            HashMap::from([("dev".to_string(), "http://localhost:8081".to_string())]),
+        );
+        write_service_config_preserving(&config_path, &config).unwrap();
+
+        let written = read_toml(&config_path).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(
+            written["services"]["@gingersociety/IAMService"],
+            toml_value(r#"dev = "${SERVICES_TOML_TEST_IAM:-http://localhost:8080}/iam""#)
+        );
+        assert_eq!(
+            written["services"]["@gingersociety/MetadataService"]["dev"].as_str(),
+            Some("http://localhost:8081")
+        );
+        assert_eq!(written["spec_lint"], toml_value(r#"inline_schema = "off""#));
+    }
+}
@@ -0,0 +1,725 @@
+use std::{collections::HashSet, fmt};
+
+use serde_json::Value;
+
+use crate::spec_lint::HTTP_METHODS;
+
+// Guards against `$ref`s pointing to each other while resolving them
+const MAX_SCHEMA_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeKind {
+    RemovedOperation,
+    RemovedResponse,
+    RemovedResponseField,
+    NewRequiredRequestField,
+    TypeChanged,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            ChangeKind::RemovedOperation => "removed operation",
+            ChangeKind::RemovedResponse => "removed response",
+            ChangeKind::RemovedResponseField => "removed response field",
+            ChangeKind::NewRequiredRequestField => "new required request field",
+            ChangeKind::TypeChanged => "type changed",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpecChange {
+    pub kind: ChangeKind,
+    /// `METHOD /path` of the affected operation
+    pub operation: String,
+    /// operationId of the affected operation in the published spec, if it had one
+    pub operation_id: Option<String>,
+    pub detail: String,
+}
+
+impl fmt::Display for SpecChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.kind, self.operation)?;
+        if !self.detail.is_empty() {
+            write!(f, " : {}", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares the published spec (`old`) with the one about to be published (`new`)
+/// and returns every change that can break an existing consumer.
+pub fn diff_specs(old: &Value, new: &Value) -> Vec<SpecChange> {
+    let mut changes = vec![];
+
+    let empty = serde_json::Map::new();
+    let old_paths = old
+        .get("paths")
+        .and_then(|p| p.as_object())
+        .unwrap_or(&empty);
+    let new_paths = new
+        .get("paths")
+        .and_then(|p| p.as_object())
+        .unwrap_or(&empty);
+
+    for (path, old_item) in old_paths {
+        // A removed path removes each of its operations, reported one by one so that consumers
+        // calling them are matched by operationId
+        let new_item = new_paths.get(path);
+
+        for method in HTTP_METHODS {
+            let old_op = match old_item.get(method) {
+                Some(op) => op,
+                None => continue,
+            };
+            let operation = format!("{} {}", method.to_uppercase(), path);
+            let operation_id = old_op
+                .get("operationId")
+                .and_then(|id| id.as_str())
+                .map(|id| id.to_string());
+
+            let new_op = match new_item.and_then(|item| item.get(method)) {
+                Some(op) => op,
+                None => {
+                    changes.push(SpecChange {
+                        kind: ChangeKind::RemovedOperation,
+                        operation,
+                        operation_id,
+                        detail: match new_item {
+                            Some(_) => String::new(),
+                            None => "path removed".to_string(),
+                        },
+                    });
+                    continue;
+                }
+            };
+
+            let mut op_changes = vec![];
+            diff_parameters(old, old_op, new, new_op, &mut op_changes);
+            diff_request_bodies(old, old_op, new, new_op, &mut op_changes);
+            diff_responses(old, old_op, new, new_op, &mut op_changes);
+
+            for (kind, detail) in op_changes {
+                changes.push(SpecChange {
+                    kind,
+                    operation: operation.clone(),
+                    operation_id: operation_id.clone(),
+                    detail,
+                });
+            }
+        }
+    }
+
+    changes
+}
+
+/// Extracts the major component of a `x.y.z[-channel.n]` version string
+pub fn major_version(version: &str) -> Option<u64> {
+    version
+        .trim_start_matches('v')
+        .split('.')
+        .next()
+        .and_then(|major| major.parse().ok())
+}
+
+fn resolve<'a>(doc: &'a Value, value: &'a Value) -> &'a Value {
+    let mut current = value;
+    for _ in 0..MAX_SCHEMA_DEPTH {
+        match current.get("$ref").and_then(|r| r.as_str()) {
+            Some(reference) => {
+                let pointer = reference.trim_start_matches('#');
+                match doc.pointer(pointer) {
+                    Some(target) => current = target,
+                    None => return current,
+                }
+            }
+            None => return current,
+        }
+    }
+    current
+}
+
+/// The `$ref`s of the old and new schema being compared, `None` when neither is one
+type RefPair = (Option<String>, Option<String>);
+
+fn ref_pair(old_schema: &Value, new_schema: &Value) -> Option<RefPair> {
+    let reference = |schema: &Value| {
+        schema
+            .get("$ref")
+            .and_then(|r| r.as_str())
+            .map(str::to_string)
+    };
+    match (reference(old_schema), reference(new_schema)) {
+        (None, None) => None,
+        refs => Some(refs),
+    }
+}
+
+fn schema_type(doc: &Value, schema: &Value) -> Option<String> {
+    let schema = resolve(doc, schema);
+    match schema.get("type") {
+        Some(Value::String(t)) => Some(t.clone()),
+        Some(Value::Array(types)) => Some(
+            types
+                .iter()
+                .filter_map(|t| t.as_str())
+                .filter(|t| *t != "null")
+                .collect::<Vec<&str>>()
+                .join("|"),
+        ),
+        _ => {
+            if schema.get("properties").is_some() {
+                Some("object".to_string())
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn required_fields(schema: &Value) -> HashSet<String> {
+    schema
+        .get("required")
+        .and_then(|r| r.as_array())
+        .map(|fields| {
+            fields
+                .iter()
+                .filter_map(|f| f.as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn json_schema<'a>(doc: &'a Value, holder: &'a Value) -> Option<&'a Value> {
+    let holder = resolve(doc, holder);
+    let content = holder.get("content")?.as_object()?;
+    let media = content
+        .get("application/json")
+        .or_else(|| content.values().next())?;
+    media.get("schema")
+}
+
+fn diff_parameters(
+    old_doc: &Value,
+    old_op: &Value,
+    new_doc: &Value,
+    new_op: &Value,
+    changes: &mut Vec<(ChangeKind, String)>,
+) {
+    let collect = |doc: &Value, op: &Value| -> Vec<(String, String, bool, Option<String>)> {
+        op.get("parameters")
+            .and_then(|p| p.as_array())
+            .map(|params| {
+                params
+                    .iter()
+                    .map(|p| resolve(doc, p))
+                    .filter_map(|p| {
+                        let name = p.get("name")?.as_str()?.to_string();
+                        let location = p.get("in")?.as_str()?.to_string();
+                        let required = p.get("required").and_then(|r| r.as_bool()) == Some(true);
+                        let ty = p.get("schema").and_then(|s| schema_type(doc, s));
+                        Some((name, location, required, ty))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let old_params = collect(old_doc, old_op);
+    let new_params = collect(new_doc, new_op);
+
+    for (name, location, required, ty) in &new_params {
+        let existing = old_params
+            .iter()
+            .find(|(n, l, _, _)| n == name && l == location);
+        match existing {
+            None if *required => changes.push((
+                ChangeKind::NewRequiredRequestField,
+                format!("{} parameter `{}`", location, name),
+            )),
+            Some((_, _, old_required, old_ty)) => {
+                if *required && !old_required {
+                    changes.push((
+                        ChangeKind::NewRequiredRequestField,
+                        format!("{} parameter `{}` is now required", location, name),
+                    ));
+                }
+                if let (Some(old_ty), Some(ty)) = (old_ty, ty) {
+                    if old_ty != ty {
+                        changes.push((
+                            ChangeKind::TypeChanged,
+                            format!("{} parameter `{}` : {} -> {}", location, name, old_ty, ty),
+                        ));
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+fn diff_request_bodies(
+    old_doc: &Value,
+    old_op: &Value,
+    new_doc: &Value,
+    new_op: &Value,
+    changes: &mut Vec<(ChangeKind, String)>,
+) {
+    let new_schema = match new_op
+        .get("requestBody")
+        .and_then(|b| json_schema(new_doc, b))
+    {
+        Some(schema) => schema,
+        None => return,
+    };
+
+    match old_op
+        .get("requestBody")
+        .and_then(|b| json_schema(old_doc, b))
+    {
+        Some(old_schema) => diff_request_schema(
+            old_doc,
+            old_schema,
+            new_doc,
+            new_schema,
+            "body",
+            &mut Vec::new(),
+            changes,
+        ),
+        None => {
+            let required = new_op
+                .get("requestBody")
+                .map(|b| resolve(new_doc, b))
+                .and_then(|b| b.get("required"))
+                .and_then(|r| r.as_bool())
+                == Some(true);
+            if required {
+                changes.push((
+                    ChangeKind::NewRequiredRequestField,
+                    "request body is now required".to_string(),
+                ));
+            }
+        }
+    }
+}
+
+fn diff_request_schema(
+    old_doc: &Value,
+    old_schema: &Value,
+    new_doc: &Value,
+    new_schema: &Value,
+    location: &str,
+    visited: &mut Vec<RefPair>,
+    changes: &mut Vec<(ChangeKind, String)>,
+) {
+    // A `$ref` already walked on this path is a recursive schema, its fields were compared above
+    let refs = ref_pair(old_schema, new_schema);
+    if matches!(&refs, Some(refs) if visited.contains(refs)) {
+        return;
+    }
+    let old_schema = resolve(old_doc, old_schema);
+    let new_schema = resolve(new_doc, new_schema);
+
+    if let (Some(old_ty), Some(new_ty)) = (
+        schema_type(old_doc, old_schema),
+        schema_type(new_doc, new_schema),
+    ) {
+        if old_ty != new_ty {
+            changes.push((
+                ChangeKind::TypeChanged,
+                format!("{} : {} -> {}", location, old_ty, new_ty),
+            ));
+            return;
+        }
+    }
+    let walked = refs.is_some();
+    visited.extend(refs);
+
+    let old_required = required_fields(old_schema);
+    for field in required_fields(new_schema) {
+        if !old_required.contains(&field) {
+            changes.push((
+                ChangeKind::NewRequiredRequestField,
+                format!("{}.{}", location, field),
+            ));
+        }
+    }
+
+    if let (Some(old_props), Some(new_props)) = (
+        old_schema.get("properties").and_then(|p| p.as_object()),
+        new_schema.get("properties").and_then(|p| p.as_object()),
+    ) {
+        for (field, new_prop) in new_props {
+            if let Some(old_prop) = old_props.get(field) {
+                diff_request_schema(
+                    old_doc,
+                    old_prop,
+                    new_doc,
+                    new_prop,
+                    &format!("{}.{}", location, field),
+                    visited,
+                    changes,
+                );
+            }
+        }
+    }
+
+    if let (Some(old_items), Some(new_items)) = (old_schema.get("items"), new_schema.get("items")) {
+        diff_request_schema(
+            old_doc,
+            old_items,
+            new_doc,
+            new_items,
+            &format!("{}[]", location),
+            visited,
+            changes,
+        );
+    }
+    if walked {
+        visited.pop();
+    }
+}
+
+fn diff_responses(
+    old_doc: &Value,
+    old_op: &Value,
+    new_doc: &Value,
+    new_op: &Value,
+    changes: &mut Vec<(ChangeKind, String)>,
+) {
+    let old_responses = match old_op.get("responses").and_then(|r| r.as_object()) {
+        Some(r) => r,
+        None => return,
+    };
+
+    for (status, old_response) in old_responses {
+        // Only the success payloads are part of what consumers deserialize
+        if !status.starts_with('2') {
+            continue;
+        }
+        let new_response = match new_op.get("responses").and_then(|r| r.get(status)) {
+            Some(r) => r,
+            None => {
+                changes.push((ChangeKind::RemovedResponse, format!("response {}", status)));
+                continue;
+            }
+        };
+        if let (Some(old_schema), Some(new_schema)) = (
+            json_schema(old_doc, old_response),
+            json_schema(new_doc, new_response),
+        ) {
+            diff_response_schema(
+                old_doc,
+                old_schema,
+                new_doc,
+                new_schema,
+                &format!("response {}", status),
+                &mut Vec::new(),
+                changes,
+            );
+        }
+    }
+}
+
+fn diff_response_schema(
+    old_doc: &Value,
+    old_schema: &Value,
+    new_doc: &Value,
+    new_schema: &Value,
+    location: &str,
+    visited: &mut Vec<RefPair>,
+    changes: &mut Vec<(ChangeKind, String)>,
+) {
+    // A `$ref` already walked on this path is a recursive schema, its fields were compared above
+    let refs = ref_pair(old_schema, new_schema);
+    if matches!(&refs, Some(refs) if visited.contains(refs)) {
+        return;
+    }
+    let old_schema = resolve(old_doc, old_schema);
+    let new_schema = resolve(new_doc, new_schema);
+
+    if let (Some(old_ty), Some(new_ty)) = (
+        schema_type(old_doc, old_schema),
+        schema_type(new_doc, new_schema),
+    ) {
+        if old_ty != new_ty {
+            changes.push((
+                ChangeKind::TypeChanged,
+                format!("{} : {} -> {}", location, old_ty, new_ty),
+            ));
+            return;
+        }
+    }
+    let walked = refs.is_some();
+    visited.extend(refs);
+
+    if let Some(old_props) = old_schema.get("properties").and_then(|p| p.as_object()) {
+        let empty = serde_json::Map::new();
+        let new_props = new_schema
+            .get("properties")
+            .and_then(|p| p.as_object())
+            .unwrap_or(&empty);
+        for (field, old_prop) in old_props {
+            match new_props.get(field) {
+                Some(new_prop) => diff_response_schema(
+                    old_doc,
+                    old_prop,
+                    new_doc,
+                    new_prop,
+                    &format!("{}.{}", location, field),
+                    visited,
+                    changes,
+                ),
+                None => changes.push((
+                    ChangeKind::RemovedResponseField,
+                    format!("{}.{}", location, field),
+                )),
+            }
+        }
+    }
+
+    if let (Some(old_items), Some(new_items)) = (old_schema.get("items"), new_schema.get("items")) {
+        diff_response_schema(
+            old_doc,
+            old_items,
+            new_doc,
+            new_items,
+            &format!("{}[]", location),
+            visited,
+            changes,
+        );
+    }
+    if walked {
+        visited.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn spec(paths: Value) -> Value {
+        json!({
+            "openapi": "3.0.0",
+            "paths": paths,
+            "components": {
+                "schemas": {
+                    "User": {
+                        "type": "object",
+                        "required": ["id"],
+                        "properties": {
+                            "id": { "type": "string" },
+                            "email": { "type": "string" },
+                            "manager": { "$ref": "#/components/schemas/User" }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    fn user_response() -> Value {
+        json!({
+            "content": {
+                "application/json": { "schema": { "$ref": "#/components/schemas/User" } }
+            }
+        })
+    }
+
+    fn users_api() -> Value {
+        spec(json!({
+            "/users/{id}": {
+                "get": {
+                    "operationId": "getUser",
+                    "parameters": [
+                        { "name": "id", "in": "path", "required": true, "schema": { "type": "string" } }
+                    ],
+                    "responses": { "200": user_response(), "404": { "description": "Not found" } }
+                },
+                "delete": {
+                    "operationId": "deleteUser",
+                    "responses": { "204": { "description": "Deleted" } }
+                }
+            }
+        }))
+    }
+
+    fn summary(changes: &[SpecChange]) -> Vec<(ChangeKind, &str, Option<&str>, &str)> {
+        changes
+            .iter()
+            .map(|change| {
+                (
+                    change.kind.clone(),
+                    change.operation.as_str(),
+                    change.operation_id.as_deref(),
+                    change.detail.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_specs_have_no_changes() {
+        assert!(diff_specs(&users_api(), &users_api()).is_empty());
+    }
+
+    #[test]
+    fn additions_are_not_breaking() {
+        let mut new = users_api();
+        new["paths"]["/users"] = json!({
+            "post": { "operationId": "createUser", "responses": { "201": user_response() } }
+        });
+        new["paths"]["/users/{id}"]["get"]["parameters"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({ "name": "expand", "in": "query", "schema": { "type": "boolean" } }));
+        new["components"]["schemas"]["User"]["properties"]["name"] = json!({ "type": "string" });
+        assert!(diff_specs(&users_api(), &new).is_empty());
+    }
+
+    #[test]
+    fn a_removed_path_removes_each_of_its_operations() {
+        let changes = diff_specs(&users_api(), &spec(json!({})));
+        assert_eq!(
+            summary(&changes),
+            vec![
+                (
+                    ChangeKind::RemovedOperation,
+                    "GET /users/{id}",
+                    Some("getUser"),
+                    "path removed"
+                ),
+                (
+                    ChangeKind::RemovedOperation,
+                    "DELETE /users/{id}",
+                    Some("deleteUser"),
+                    "path removed"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_removed_method_is_a_removed_operation() {
+        let mut new = users_api();
+        new["paths"]["/users/{id}"]
+            .as_object_mut()
+            .unwrap()
+            .remove("delete");
+        assert_eq!(
+            summary(&diff_specs(&users_api(), &new)),
+            vec![(
+                ChangeKind::RemovedOperation,
+                "DELETE /users/{id}",
+                Some("deleteUser"),
+                ""
+            )]
+        );
+    }
+
+    #[test]
+    fn only_removed_success_responses_are_breaking() {
+        let mut new = users_api();
+        let responses = new["paths"]["/users/{id}"]["get"]["responses"]
+            .as_object_mut()
+            .unwrap();
+        responses.remove("404");
+        assert!(diff_specs(&users_api(), &new).is_empty());
+
+        new["paths"]["/users/{id}"]["get"]["responses"]
+            .as_object_mut()
+            .unwrap()
+            .remove("200");
+        assert_eq!(
+            summary(&diff_specs(&users_api(), &new)),
+            vec![(
+                ChangeKind::RemovedResponse,
+                "GET /users/{id}",
+                Some("getUser"),
+                "response 200"
+            )]
+        );
+    }
+
+    #[test]
+    fn removed_response_fields_are_found_through_refs() {
+        let mut new = users_api();
+        new["components"]["schemas"]["User"]["properties"]
+            .as_object_mut()
+            .unwrap()
+            .remove("email");
+        // `manager` refers back to `User`, the removal is reported once
+        assert_eq!(
+            summary(&diff_specs(&users_api(), &new)),
+            vec![(
+                ChangeKind::RemovedResponseField,
+                "GET /users/{id}",
+                Some("getUser"),
+                "response 200.email"
+            )]
+        );
+    }
+
+    #[test]
+    fn new_required_request_fields_and_type_changes_are_breaking() {
+        let mut new = users_api();
+        let get = &mut new["paths"]["/users/{id}"]["get"];
+        get["parameters"][0]["schema"]["type"] = json!("integer");
+        get["parameters"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({ "name": "tenant", "in": "header", "required": true }));
+        assert_eq!(
+            summary(&diff_specs(&users_api(), &new)),
+            vec![
+                (
+                    ChangeKind::TypeChanged,
+                    "GET /users/{id}",
+                    Some("getUser"),
+                    "path parameter `id` : string -> integer"
+                ),
+                (
+                    ChangeKind::NewRequiredRequestField,
+                    "GET /users/{id}",
+                    Some("getUser"),
+                    "header parameter `tenant`"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn recursive_request_bodies_are_walked_once() {
+        let with_body = || {
+            let mut api = users_api();
+            api["paths"]["/users/{id}"]["put"] = json!({
+                "operationId": "updateUser",
+                "requestBody": user_response(),
+                "responses": { "200": user_response() }
+            });
+            api
+        };
+        let mut new = with_body();
+        new["components"]["schemas"]["User"]["required"] = json!(["id", "email"]);
+        assert_eq!(
+            summary(&diff_specs(&with_body(), &new)),
+            vec![(
+                ChangeKind::NewRequiredRequestField,
+                "PUT /users/{id}",
+                Some("updateUser"),
+                "body.email"
+            )]
+        );
+    }
+
+    #[test]
+    fn major_versions_are_parsed_with_or_without_a_prefix() {
+        assert_eq!(major_version("2.4.1"), Some(2));
+        assert_eq!(major_version("v3.0.0-nightly.1"), Some(3));
+        assert_eq!(major_version("latest"), None);
+    }
+}
@@ -0,0 +1,573 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use serde_json::Value;
+
+use crate::services_toml::config_section_or_exit;
+
+/// Keys of an OpenAPI path item holding operations
+pub const HTTP_METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Off,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn parse(value: &str) -> Option<Severity> {
+        match value.to_lowercase().as_str() {
+            "off" => Some(Severity::Off),
+            "warn" | "warning" => Some(Severity::Warning),
+            "error" => Some(Severity::Error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Severity::Off => "off",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    MissingOperationId,
+    DuplicateOperationId,
+    MissingInfoVersion,
+    UndocumentedErrorResponses,
+    MissingSecuritySchemes,
+    InlineSchema,
+}
+
+impl Rule {
+    const ALL: [Rule; 6] = [
+        Rule::MissingOperationId,
+        Rule::DuplicateOperationId,
+        Rule::MissingInfoVersion,
+        Rule::UndocumentedErrorResponses,
+        Rule::MissingSecuritySchemes,
+        Rule::InlineSchema,
+    ];
+
+    /// Key of the rule in the `[spec_lint]` section of services.toml
+    fn key(&self) -> &'static str {
+        match self {
+            Rule::MissingOperationId => "missing_operation_id",
+            Rule::DuplicateOperationId => "duplicate_operation_id",
+            Rule::MissingInfoVersion => "missing_info_version",
+            Rule::UndocumentedErrorResponses => "undocumented_error_responses",
+            Rule::MissingSecuritySchemes => "missing_security_schemes",
+            Rule::InlineSchema => "inline_schema",
+        }
+    }
+
+    fn default_severity(&self) -> Severity {
+        match self {
+            Rule::MissingOperationId | Rule::DuplicateOperationId | Rule::MissingInfoVersion => {
+                Severity::Error
+            }
+            Rule::UndocumentedErrorResponses
+            | Rule::MissingSecuritySchemes
+            | Rule::InlineSchema => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LintFinding {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}] {} : {}",
+            self.severity,
+            self.rule.key(),
+            self.location,
+            self.message
+        )
+    }
+}
+
+/// Rule severities, overridable in services.toml:
+///
+/// ```toml
+/// [spec_lint]
+/// inline_schema = "off"
+/// undocumented_error_responses = "error"
+/// ```
+pub struct LintConfig {
+    severities: HashMap<Rule, Severity>,
+}
+
+impl LintConfig {
+    pub fn from_services_toml(config_path: &Path) -> LintConfig {
+        let mut severities: HashMap<Rule, Severity> = Rule::ALL
+            .iter()
+            .map(|rule| (*rule, rule.default_severity()))
+            .collect();
+
+        if let Some(section) = config_section_or_exit(config_path, "spec_lint") {
+            for rule in Rule::ALL {
+                if let Some(value) = section.get(rule.key()).and_then(|v| v.as_str()) {
+                    match Severity::parse(value) {
+                        Some(severity) => {
+                            severities.insert(rule, severity);
+                        }
+                        None => eprintln!(
+                            "Unknown severity {:?} for {} in [spec_lint], using {}",
+                            value,
+                            rule.key(),
+                            rule.default_severity()
+                        ),
+                    }
+                }
+            }
+        }
+
+        LintConfig { severities }
+    }
+
+    fn severity(&self, rule: Rule) -> Severity {
+        *self
+            .severities
+            .get(&rule)
+            .unwrap_or(&rule.default_severity())
+    }
+}
+
+fn operations(spec: &Value) -> Vec<(String, &Value)> {
+    let mut result = vec![];
+    if let Some(paths) = spec.get("paths").and_then(|p| p.as_object()) {
+        for (path, item) in paths {
+            for method in HTTP_METHODS {
+                if let Some(operation) = item.get(method) {
+                    result.push((format!("{} {}", method.to_uppercase(), path), operation));
+                }
+            }
+        }
+    }
+    result
+}
+
+fn is_inline_object(schema: &Value) -> bool {
+    if schema.get("$ref").is_some() {
+        return false;
+    }
+    if schema.get("properties").is_some() || schema.get("allOf").is_some() {
+        return true;
+    }
+    schema.get("items").map_or(false, is_inline_object)
+}
+
+fn content_schemas(holder: &Value) -> Vec<&Value> {
+    holder
+        .get("content")
+        .and_then(|c| c.as_object())
+        .map(|content| {
+            content
+                .values()
+                .filter_map(|media| media.get("schema"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Runs the built in rule set on the spec, findings turned `off` are not reported
+pub fn lint_spec(spec: &Value, config: &LintConfig) -> Vec<LintFinding> {
+    let mut findings = vec![];
+    let mut report = |rule: Rule, location: String, message: String| {
+        let severity = config.severity(rule);
+        if severity != Severity::Off {
+            findings.push(LintFinding {
+                rule,
+                severity,
+                location,
+                message,
+            });
+        }
+    };
+
+    let version = spec
+        .get("info")
+        .and_then(|info| info.get("version"))
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+    if version.is_empty() {
+        report(
+            Rule::MissingInfoVersion,
+            "info".to_string(),
+            "info.version is required to verify the published version".to_string(),
+        );
+    }
+
+    let security_schemes = spec
+        .get("components")
+        .and_then(|c| c.get("securitySchemes"))
+        .and_then(|s| s.as_object());
+    if security_schemes.map_or(true, |schemes| schemes.is_empty()) {
+        report(
+            Rule::MissingSecuritySchemes,
+            "components.securitySchemes".to_string(),
+            "no security schemes are declared".to_string(),
+        );
+    }
+
+    let mut seen_ids: HashMap<String, String> = HashMap::new();
+    for (location, operation) in operations(spec) {
+        match operation.get("operationId").and_then(|id| id.as_str()) {
+            None | Some("") => report(
+                Rule::MissingOperationId,
+                location.clone(),
+                "generated clients fall back to path based method names".to_string(),
+            ),
+            Some(id) => {
+                if let Some(first) = seen_ids.get(id) {
+                    report(
+                        Rule::DuplicateOperationId,
+                        location.clone(),
+                        format!("operationId `{}` is also used by {}", id, first),
+                    );
+                } else {
+                    seen_ids.insert(id.to_string(), location.clone());
+                }
+            }
+        }
+
+        let responses = operation.get("responses").and_then(|r| r.as_object());
+        let documents_errors = responses.map_or(false, |responses| {
+            responses.keys().any(|status| {
+                status.starts_with('4') || status.starts_with('5') || status == "default"
+            })
+        });
+        if !documents_errors {
+            report(
+                Rule::UndocumentedErrorResponses,
+                location.clone(),
+                "no 4xx / 5xx / default response is documented".to_string(),
+            );
+        }
+
+        if let Some(security) = operation.get("security").and_then(|s| s.as_array()) {
+            for requirement in security.iter().filter_map(|r| r.as_object()) {
+                for scheme in requirement.keys() {
+                    if !security_schemes.map_or(false, |schemes| schemes.contains_key(scheme)) {
+                        report(
+                            Rule::MissingSecuritySchemes,
+                            location.clone(),
+                            format!("security scheme `{}` is not declared", scheme),
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(body) = operation.get("requestBody") {
+            if content_schemas(body).into_iter().any(is_inline_object) {
+                report(
+                    Rule::InlineSchema,
+                    location.clone(),
+                    "inline request body schema, move it to components.schemas".to_string(),
+                );
+            }
+        }
+        if let Some(responses) = responses {
+            for (status, response) in responses {
+                if content_schemas(response).into_iter().any(is_inline_object) {
+                    report(
+                        Rule::InlineSchema,
+                        location.clone(),
+                        format!(
+                            "inline schema in response {}, move it to components.schemas",
+                            status
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn default_config() -> LintConfig {
+        LintConfig::from_services_toml(Path::new("no/such/dir/services.toml"))
+    }
+
+    /// Writes a services.toml of its own for each test, they run in parallel
+    fn config_from(test: &str, content: &str) -> LintConfig {
+        let dir = env::temp_dir().join(format!("spec_lint_{}_{}", process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("services.toml");
+        fs::write(&config_path, content).unwrap();
+        let config = LintConfig::from_services_toml(&config_path);
+        fs::remove_dir_all(&dir).unwrap();
+        config
+    }
+
+    /// A spec that passes every rule
+    fn clean_spec() -> Value {
+        json!({
+            "openapi": "3.0.0",
+            "info": { "title": "Users", "version": "1.0.0" },
+            "components": {
+                "securitySchemes": { "bearer": { "type": "http", "scheme": "bearer" } },
+                "schemas": {
+                    "User": { "type": "object", "properties": { "id": { "type": "string" } } }
+                }
+            },
+            "paths": {
+                "/users/{id}": {
+                    "get": {
+                        "operationId": "getUser",
+                        "security": [{ "bearer": [] }],
+                        "responses": {
+                            "200": {
+                                "content": {
+                                    "application/json": {
+                                        "schema": { "$ref": "#/components/schemas/User" }
+                                    }
+                                }
+                            },
+                            "404": { "description": "Not found" }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    fn findings(spec: &Value, config: &LintConfig) -> Vec<(Rule, Severity, String)> {
+        lint_spec(spec, config)
+            .into_iter()
+            .map(|finding| (finding.rule, finding.severity, finding.location))
+            .collect()
+    }
+
+    fn get_user(spec: &mut Value) -> &mut Value {
+        &mut spec["paths"]["/users/{id}"]["get"]
+    }
+
+    #[test]
+    fn a_clean_spec_has_no_findings() {
+        assert!(lint_spec(&clean_spec(), &default_config()).is_empty());
+    }
+
+    #[test]
+    fn operations_need_an_operation_id() {
+        let mut spec = clean_spec();
+        get_user(&mut spec)["operationId"] = json!("");
+        assert_eq!(
+            findings(&spec, &default_config()),
+            vec![(
+                Rule::MissingOperationId,
+                Severity::Error,
+                "GET /users/{id}".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn operation_ids_are_unique() {
+        let mut spec = clean_spec();
+        let get = get_user(&mut spec).clone();
+        spec["paths"]["/users/{id}"]["put"] = get;
+        let lint = lint_spec(&spec, &default_config());
+        assert_eq!(lint.len(), 1);
+        assert_eq!(lint[0].rule, Rule::DuplicateOperationId);
+        assert_eq!(lint[0].severity, Severity::Error);
+        assert_eq!(lint[0].location, "PUT /users/{id}");
+        assert_eq!(
+            lint[0].message,
+            "operationId `getUser` is also used by GET /users/{id}"
+        );
+    }
+
+    #[test]
+    fn info_version_is_required() {
+        let mut spec = clean_spec();
+        spec["info"].as_object_mut().unwrap().remove("version");
+        assert_eq!(
+            findings(&spec, &default_config()),
+            vec![(
+                Rule::MissingInfoVersion,
+                Severity::Error,
+                "info".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn error_responses_are_documented() {
+        let mut spec = clean_spec();
+        get_user(&mut spec)["responses"]
+            .as_object_mut()
+            .unwrap()
+            .remove("404");
+        assert_eq!(
+            findings(&spec, &default_config()),
+            vec![(
+                Rule::UndocumentedErrorResponses,
+                Severity::Warning,
+                "GET /users/{id}".to_string()
+            )]
+        );
+
+        get_user(&mut spec)["responses"]["default"] = json!({ "description": "Error" });
+        assert!(findings(&spec, &default_config()).is_empty());
+    }
+
+    #[test]
+    fn security_schemes_are_declared() {
+        let mut spec = clean_spec();
+        get_user(&mut spec)["security"] = json!([{ "apiKey": [] }]);
+        assert_eq!(
+            findings(&spec, &default_config()),
+            vec![(
+                Rule::MissingSecuritySchemes,
+                Severity::Warning,
+                "GET /users/{id}".to_string()
+            )]
+        );
+
+        spec["components"]
+            .as_object_mut()
+            .unwrap()
+            .remove("securitySchemes");
+        assert_eq!(
+            findings(&spec, &default_config()),
+            vec![
+                (
+                    Rule::MissingSecuritySchemes,
+                    Severity::Warning,
+                    "components.securitySchemes".to_string()
+                ),
+                (
+                    Rule::MissingSecuritySchemes,
+                    Severity::Warning,
+                    "GET /users/{id}".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn object_schemas_belong_in_components() {
+        let mut spec = clean_spec();
+        get_user(&mut spec)["requestBody"] = json!({
+            "content": {
+                "application/json": {
+                    "schema": { "type": "object", "properties": { "name": { "type": "string" } } }
+                }
+            }
+        });
+        get_user(&mut spec)["responses"]["200"]["content"]["application/json"]["schema"] = json!({
+            "type": "array",
+            "items": { "allOf": [{ "$ref": "#/components/schemas/User" }] }
+        });
+        let lint = lint_spec(&spec, &default_config());
+        assert_eq!(
+            lint.iter()
+                .map(|finding| (finding.rule, finding.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    Rule::InlineSchema,
+                    "inline request body schema, move it to components.schemas"
+                ),
+                (
+                    Rule::InlineSchema,
+                    "inline schema in response 200, move it to components.schemas"
+                ),
+            ]
+        );
+
+        // Arrays of referenced schemas and primitives are fine
+        get_user(&mut spec)["requestBody"]["content"]["application/json"]["schema"] =
+            json!({ "type": "string" });
+        get_user(&mut spec)["responses"]["200"]["content"]["application/json"]["schema"] = json!({
+            "type": "array",
+            "items": { "$ref": "#/components/schemas/User" }
+        });
+        assert!(lint_spec(&spec, &default_config()).is_empty());
+    }
+
+    #[test]
+    fn severities_are_overridden_in_services_toml() {
+        let config = config_from(
+            "overrides",
+            r#"
+[spec_lint]
+inline_schema = "off"
+undocumented_error_responses = "Error"
+missing_operation_id = "warn"
+missing_info_version = "loud"
+"#,
+        );
+        assert_eq!(config.severity(Rule::InlineSchema), Severity::Off);
+        assert_eq!(
+            config.severity(Rule::UndocumentedErrorResponses),
+            Severity::Error
+        );
+        assert_eq!(config.severity(Rule::MissingOperationId), Severity::Warning);
+        // Unknown severities keep the default
+        assert_eq!(config.severity(Rule::MissingInfoVersion), Severity::Error);
+        assert_eq!(
+            config.severity(Rule::MissingSecuritySchemes),
+            Severity::Warning
+        );
+
+        let mut spec = clean_spec();
+        get_user(&mut spec)["operationId"] = json!("");
+        get_user(&mut spec)["responses"] = json!({
+            "200": { "content": { "application/json": { "schema": { "properties": {} } } } }
+        });
+        assert_eq!(
+            findings(&spec, &config),
+            vec![
+                (
+                    Rule::MissingOperationId,
+                    Severity::Warning,
+                    "GET /users/{id}".to_string()
+                ),
+                (
+                    Rule::UndocumentedErrorResponses,
+                    Severity::Error,
+                    "GET /users/{id}".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn defaults_apply_without_a_spec_lint_section() {
+        let config = config_from("no_section", "organization_id = \"gingersociety\"\n");
+        for rule in Rule::ALL {
+            assert_eq!(config.severity(rule), rule.default_severity());
+        }
+        assert_eq!(Severity::parse("WARNING"), Some(Severity::Warning));
+        assert_eq!(Severity::parse("loud"), None);
+    }
+}
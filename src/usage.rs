@@ -0,0 +1,575 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use colored::Colorize;
+use ginger_shared_rs::{ServiceConfig, LANG};
+use serde_json::{json, Value as JsonValue};
+
+use crate::{
+    impact::USAGE_EXTENSION, services_toml::read_services_config, spec_lint::HTTP_METHODS,
+};
+
+const IGNORED_DIRS: [&str; 10] = [
+    "target",
+    "node_modules",
+    ".git",
+    ".ginger.tmp",
+    "dist",
+    "build",
+    "venv",
+    ".venv",
+    "__pycache__",
+    ".next",
+];
+
+/// Dependency slug (`@org/Service`) -> operationIds called from this project
+pub type UsageSet = BTreeMap<String, BTreeSet<String>>;
+
+fn source_extensions(lang: &LANG) -> Vec<&'static str> {
+    match lang {
+        LANG::Rust => vec!["rs"],
+        LANG::TS => vec!["ts", "tsx", "js", "jsx"],
+        LANG::Python => vec!["py"],
+        LANG::Shell => vec!["sh"],
+    }
+}
+
+fn collect_files(dir: &Path, extensions: &[&str], excluded: &[PathBuf], files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if path.is_dir() {
+            if IGNORED_DIRS.contains(&file_name.as_str())
+                || excluded.iter().any(|ex| path.ends_with(ex))
+            {
+                continue;
+            }
+            collect_files(&path, extensions, excluded, files);
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map_or(false, |ext| extensions.contains(&ext))
+        {
+            files.push(path);
+        }
+    }
+}
+
+/// Source files of the project for the given language, skipping build outputs, vendored
+/// dependencies and the generated clients directory
+pub fn source_files(lang: &LANG, clients_dir: &Option<String>) -> Vec<PathBuf> {
+    let excluded: Vec<PathBuf> = clients_dir
+        .iter()
+        .map(|dir| PathBuf::from(dir.trim_start_matches("./")))
+        .collect();
+    let mut files = vec![];
+    collect_files(
+        Path::new("."),
+        &source_extensions(lang),
+        &excluded,
+        &mut files,
+    );
+    files
+}
+
+/// Whether the source imports the generated client of `service_name`
+pub fn imports_client(content: &str, lang: &LANG, service_name: &str) -> bool {
+    match lang {
+        LANG::Rust => {
+            content.contains(&format!("{}::", service_name))
+                || content.contains(&format!("use {}", service_name))
+        }
+        LANG::TS | LANG::Python | LANG::Shell => {
+            content.contains(&format!("{}_client", service_name))
+        }
+    }
+}
+
+/// Path segment or receiver right before the identifier starting at `start`, e.g. `default_api`
+/// for `default_api::foo` or `api` for `api.foo` / `api?.foo`
+fn qualifier_before(chars: &[char], start: usize) -> Option<String> {
+    let skip_whitespace = |mut k: usize| {
+        while k > 0 && chars[k - 1].is_whitespace() {
+            k -= 1;
+        }
+        k
+    };
+    let mut k = skip_whitespace(start);
+    if k >= 2 && chars[k - 1] == ':' && chars[k - 2] == ':' {
+        k -= 2;
+    } else if k >= 1 && chars[k - 1] == '.' {
+        k -= 1;
+        if k >= 1 && chars[k - 1] == '?' {
+            k -= 1;
+        }
+    } else {
+        return None;
+    }
+    let end = skip_whitespace(k);
+    let mut k = end;
+    while k > 0 && (chars[k - 1].is_alphanumeric() || chars[k - 1] == '_') {
+        k -= 1;
+    }
+    (k < end).then(|| chars[k..end].iter().collect())
+}
+
+/// Identifiers that are immediately followed by a call, with what qualifies them:
+/// `default_api::foo(` -> (Some("default_api"), "foo"), `foo(` -> (None, "foo")
+pub fn calls_in(content: &str) -> HashSet<(Option<String>, String)> {
+    let mut calls = HashSet::new();
+    let chars: Vec<char> = content.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_alphabetic() || chars[i] == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let ident: String = chars[start..i].iter().collect();
+            let mut j = i;
+            while j < chars.len() && chars[j].is_whitespace() {
+                j += 1;
+            }
+            if j < chars.len() && chars[j] == '(' {
+                calls.insert((qualifier_before(&chars, start), ident));
+            }
+        } else {
+            i += 1;
+        }
+    }
+    calls
+}
+
+/// Names the generated API is reachable through in a source file. For Rust, the
+/// `default_api` module and the functions imported from it (`None` standing for a glob
+/// import). For TS and Python, the default import of the client and the variables holding a
+/// `DefaultApi`
+fn api_qualifiers(content: &str, lang: &LANG, service_name: &str) -> HashSet<Option<String>> {
+    let mut qualifiers = HashSet::new();
+    for line in content.lines().map(str::trim) {
+        match lang {
+            LANG::Rust => {
+                if let Some((_, imported)) = line
+                    .strip_prefix("use ")
+                    .and_then(|path| path.split_once("default_api::"))
+                {
+                    if imported.trim_end_matches(';').trim() == "*" {
+                        qualifiers.insert(None);
+                    }
+                }
+            }
+            LANG::TS => {
+                if let Some(import) = line
+                    .strip_prefix("import ")
+                    .filter(|_| line.contains(&format!("{}_client", service_name)))
+                {
+                    let default_import: String = import
+                        .chars()
+                        .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+                        .collect();
+                    if !default_import.is_empty() && default_import != "type" {
+                        qualifiers.insert(Some(default_import));
+                    }
+                }
+            }
+            LANG::Python | LANG::Shell => {}
+        }
+        if let Some((left, _)) = line
+            .split_once('=')
+            .filter(|(_, right)| right.contains("DefaultApi("))
+        {
+            let variable = left.split(':').next().unwrap_or_default().trim();
+            let variable = variable.rsplit(['.', ' ']).next().unwrap_or_default();
+            if !variable.is_empty() {
+                qualifiers.insert(Some(variable.to_string()));
+            }
+        }
+    }
+    if let LANG::Rust = lang {
+        qualifiers.insert(Some("default_api".to_string()));
+    }
+    qualifiers
+}
+
+/// Names imported one by one from `default_api`, which Rust sources then call unqualified
+fn rust_imported_functions(content: &str) -> HashSet<String> {
+    let mut imported = HashSet::new();
+    let mut rest = content;
+    while let Some(index) = rest.find("default_api::") {
+        rest = &rest[index + "default_api::".len()..];
+        let names = match rest.strip_prefix('{') {
+            Some(group) => &group[..group.find('}').unwrap_or(group.len())],
+            None => &rest[..rest.find([';', ',', '(', '\n']).unwrap_or(rest.len())],
+        };
+        for name in names.split(',').map(str::trim) {
+            if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                imported.insert(name.to_string());
+            }
+        }
+    }
+    imported
+}
+
+/// Client methods called from a source file that imports the client of `service_name`:
+/// only the calls going through the generated API are counted, so that unrelated functions
+/// sharing a name with an operation are not
+pub fn client_calls(content: &str, lang: &LANG, service_name: &str) -> HashSet<String> {
+    let qualifiers = api_qualifiers(content, lang, service_name);
+    let imported = match lang {
+        LANG::Rust => rust_imported_functions(content),
+        _ => HashSet::new(),
+    };
+    calls_in(content)
+        .into_iter()
+        .filter(|(qualifier, name)| {
+            qualifiers.contains(qualifier) || (qualifier.is_none() && imported.contains(name))
+        })
+        .map(|(_, name)| name)
+        .collect()
+}
+
+pub fn to_snake_case(input: &str) -> String {
+    let mut output = String::new();
+    let mut previous_lower = false;
+    for c in input.chars() {
+        if c.is_uppercase() {
+            if previous_lower {
+                output.push('_');
+            }
+            output.extend(c.to_lowercase());
+            previous_lower = false;
+        } else if c == '-' || c == ' ' {
+            output.push('_');
+            previous_lower = false;
+        } else {
+            output.push(c);
+            previous_lower = c.is_lowercase() || c.is_numeric();
+        }
+    }
+    output
+}
+
+pub fn to_camel_case(input: &str) -> String {
+    let snake = to_snake_case(input);
+    let mut output = String::new();
+    let mut upper_next = false;
+    for c in snake.chars() {
+        if c == '_' {
+            upper_next = !output.is_empty();
+        } else if upper_next {
+            output.extend(c.to_uppercase());
+            upper_next = false;
+        } else {
+            output.push(c);
+        }
+    }
+    output
+}
+
+/// Name of the generated method for an operationId in the given language
+fn client_method_name(operation_id: &str, lang: &LANG) -> String {
+    match lang {
+        LANG::TS => to_camel_case(operation_id),
+        _ => to_snake_case(operation_id),
+    }
+}
+
+pub fn operation_ids(spec: &JsonValue) -> Vec<String> {
+    let mut ids = vec![];
+    if let Some(paths) = spec.get("paths").and_then(|p| p.as_object()) {
+        for item in paths.values() {
+            for operation in HTTP_METHODS.iter().filter_map(|method| item.get(method)) {
+                if let Some(id) = operation.get("operationId").and_then(|id| id.as_str()) {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+    }
+    ids
+}
+
+/// Finds any spec cached by `connect` for the service, whatever env it was generated for
+fn read_cached_spec(org_id: &str, package_name: &str) -> Option<JsonValue> {
+    let prefix = format!("{}@{}.", package_name, org_id);
+    let entry = fs::read_dir(".ginger.tmp").ok()?.flatten().find(|entry| {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        file_name.starts_with(&prefix) && file_name.ends_with(".spec.json")
+    })?;
+    let content = fs::read_to_string(entry.path()).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn split_service_slug(slug: &str) -> Option<(String, String)> {
+    let (org, name) = slug.trim_start_matches('@').split_once('/')?;
+    Some((org.to_string(), name.to_string()))
+}
+
+/// Scans the project sources and maps the calls into generated clients back to the
+/// operationIds of the specs cached under `.ginger.tmp`
+pub fn collect_usage(services_config: &ServiceConfig) -> UsageSet {
+    let files: Vec<String> = source_files(&services_config.lang, &services_config.dir)
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .collect();
+
+    let mut usage = UsageSet::new();
+
+    let services = match &services_config.services {
+        Some(services) => services,
+        None => return usage,
+    };
+
+    for slug in services.keys() {
+        let (org_id, package_name) = match split_service_slug(slug) {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let spec = match read_cached_spec(&org_id, &package_name) {
+            Some(spec) => spec,
+            None => {
+                eprintln!(
+                    "No cached spec found for {}, run {} first",
+                    slug,
+                    "ginger-connector connect".blue()
+                );
+                continue;
+            }
+        };
+
+        let methods: Vec<(String, String)> = operation_ids(&spec)
+            .into_iter()
+            .map(|id| (client_method_name(&id, &services_config.lang), id))
+            .collect();
+
+        let mut used = BTreeSet::new();
+        for content in files
+            .iter()
+            .filter(|content| imports_client(content, &services_config.lang, &package_name))
+        {
+            let calls = client_calls(content, &services_config.lang, &package_name);
+            for (method, operation_id) in &methods {
+                if calls.contains(method) {
+                    used.insert(operation_id.clone());
+                }
+            }
+        }
+        usage.insert(slug.clone(), used);
+    }
+
+    usage
+}
+
+/// Embeds the usage set into the spec that is about to be published. Consumers without a
+/// spec, like portals, publish a document holding only the usage
+pub fn attach_usage(spec: &str, usage: &UsageSet) -> String {
+    let mut spec_json: JsonValue = if spec.is_empty() {
+        json!({})
+    } else {
+        match serde_json::from_str(spec) {
+            Ok(value) => value,
+            Err(_) => return spec.to_string(),
+        }
+    };
+    if let Some(object) = spec_json.as_object_mut() {
+        object.insert(USAGE_EXTENSION.to_string(), json!(usage));
+    }
+    spec_json.to_string()
+}
+
+pub fn usage_report(config_path: &Path, as_json: bool) {
+    let services_config = match read_services_config(config_path) {
+        Ok(c) => c,
+        Err(err) => {
+            println!("{:?}", err);
+            println!(
+                "There is no service configuration found. Please use {} to add one. Exiting",
+                "ginger-connector init".blue()
+            );
+            exit(1);
+        }
+    };
+
+    let usage = collect_usage(&services_config);
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&usage).unwrap());
+        return;
+    }
+
+    for (slug, operations) in &usage {
+        println!("{}", slug.bold());
+        if operations.is_empty() {
+            println!("  {}", "no operations called".yellow());
+        }
+        for operation in operations {
+            println!("  {}", operation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn qualifier_of(source: &str, ident: &str) -> Option<String> {
+        let chars: Vec<char> = source.chars().collect();
+        let start = source.find(ident).unwrap();
+        qualifier_before(&chars, source[..start].chars().count())
+    }
+
+    fn sorted(calls: HashSet<String>) -> Vec<String> {
+        let mut calls: Vec<String> = calls.into_iter().collect();
+        calls.sort();
+        calls
+    }
+
+    #[test]
+    fn qualifiers_are_paths_or_receivers() {
+        assert_eq!(
+            qualifier_of("default_api::get_user(", "get_user"),
+            Some("default_api".to_string())
+        );
+        assert_eq!(
+            qualifier_of("api.getUser(", "getUser"),
+            Some("api".to_string())
+        );
+        assert_eq!(
+            qualifier_of("api?.getUser(", "getUser"),
+            Some("api".to_string())
+        );
+        assert_eq!(
+            qualifier_of("self.api\n    .get_user(", "get_user"),
+            Some("api".to_string())
+        );
+        assert_eq!(qualifier_of("let user = get_user(", "get_user"), None);
+        assert_eq!(qualifier_of("(1).get_user(", "get_user"), None);
+    }
+
+    #[test]
+    fn only_identifiers_followed_by_a_call_are_collected() {
+        let calls = calls_in("let user = default_api::get_user (&config, id);\nlet ids = user.ids;\nprintln!(\"{}\", helper());");
+        let expected: HashSet<(Option<String>, String)> = [
+            (Some("default_api".to_string()), "get_user".to_string()),
+            (None, "helper".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(calls, expected);
+    }
+
+    #[test]
+    fn rust_imports_from_default_api_are_listed() {
+        let source = "use IAMService::apis::default_api::{get_user, list_users};\nuse IAMService::apis::default_api::delete_user;\nuse IAMService::apis::default_api::*;";
+        let mut imported: Vec<String> = rust_imported_functions(source).into_iter().collect();
+        imported.sort();
+        assert_eq!(imported, vec!["delete_user", "get_user", "list_users"]);
+    }
+
+    #[test]
+    fn operation_ids_map_to_method_names() {
+        assert_eq!(to_snake_case("getUserByID"), "get_user_by_id");
+        assert_eq!(to_snake_case("list-users"), "list_users");
+        assert_eq!(to_snake_case("get_user"), "get_user");
+        assert_eq!(to_camel_case("get_user"), "getUser");
+        assert_eq!(to_camel_case("GetUser"), "getUser");
+        assert_eq!(to_camel_case("list-users"), "listUsers");
+    }
+
+    #[test]
+    fn rust_calls_go_through_default_api_or_its_imports() {
+        let source = r#"
+use IAMService::apis::default_api::{get_user};
+
+async fn run(config: &Configuration) {
+    get_user(config, "1").await;
+    default_api::list_users(config).await;
+    cache::delete_user("1");
+    delete_user("1");
+}
+"#;
+        assert_eq!(
+            sorted(client_calls(source, &LANG::Rust, "IAMService")),
+            vec!["get_user", "list_users"]
+        );
+
+        // With a glob import every unqualified call is a candidate, `collect_usage` then keeps
+        // the ones named after an operation
+        let glob = "use IAMService::apis::default_api::*;\nfn run() { delete_user(config); }";
+        assert_eq!(
+            sorted(client_calls(glob, &LANG::Rust, "IAMService")),
+            vec!["delete_user", "run"]
+        );
+    }
+
+    #[test]
+    fn ts_calls_go_through_the_default_import_or_a_default_api() {
+        let source = r#"
+import IAM, { DefaultApi } from "IAMService_client";
+
+const api = new DefaultApi(config);
+await api.getUser("1");
+await api?.listUsers();
+IAM.health();
+cache.deleteUser("1");
+deleteUser("1");
+"#;
+        assert_eq!(
+            sorted(client_calls(source, &LANG::TS, "IAMService")),
+            vec!["getUser", "health", "listUsers"]
+        );
+    }
+
+    #[test]
+    fn python_calls_go_through_a_default_api_instance() {
+        let source = r#"
+from IAMService_client import DefaultApi
+
+class Users:
+    def __init__(self, client):
+        self.api: DefaultApi = DefaultApi(client)
+
+    def get(self, id):
+        return self.api.get_user(id)
+
+api = DefaultApi(client)
+api.list_users()
+cache.delete_user("1")
+"#;
+        assert_eq!(
+            sorted(client_calls(source, &LANG::Python, "IAMService")),
+            vec!["get_user", "list_users"]
+        );
+    }
+
+    #[test]
+    fn usage_is_published_with_or_without_a_spec() {
+        let usage: UsageSet = [(
+            "@gingersociety/IAMService".to_string(),
+            ["getUser".to_string()].into_iter().collect(),
+        )]
+        .into_iter()
+        .collect();
+
+        let portal: JsonValue = serde_json::from_str(&attach_usage("", &usage)).unwrap();
+        assert_eq!(
+            portal,
+            json!({ USAGE_EXTENSION: { "@gingersociety/IAMService": ["getUser"] } })
+        );
+
+        let service: JsonValue =
+            serde_json::from_str(&attach_usage(r#"{"openapi": "3.0.0"}"#, &usage)).unwrap();
+        assert_eq!(service["openapi"], "3.0.0");
+        assert_eq!(
+            service[USAGE_EXTENSION]["@gingersociety/IAMService"],
+            json!(["getUser"])
+        );
+    }
+}
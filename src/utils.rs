@@ -3,7 +3,7 @@ use jsonwebtoken::{encode, EncodingKey, Header};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap},
     path::Path,
     process::{exit, Command},
     time::Duration,
@@ -13,25 +13,22 @@ use tokio::time::sleep;
 use colored::Colorize;
 use ginger_shared_rs::{
     read_db_config, read_package_metadata_file, read_releaser_config_file,
-    read_service_config_file, utils::get_package_json_info, write_db_config,
-    write_service_config_file, ISCClaims, LANG,
+    read_service_config_file, utils::get_package_json_info, write_db_config, ISCClaims,
+    ServiceConfig, LANG,
 };
 use inquire::{list_option::ListOption, validator::Validation, MultiSelect};
-use serde_json::json;
 use IAMService::apis::configuration::Configuration as IAMConfiguration;
 use MetadataService::{
     apis::{
         configuration::Configuration as MetadataConfiguration,
         default_api::{
             metadata_create_dbschema, metadata_create_or_update_package,
-            metadata_get_dbschemas_and_tables, metadata_get_package_version,
-            metadata_get_package_version_plain_text, metadata_get_service_by_id,
-            metadata_get_services_and_envs, metadata_get_user_packages, metadata_update_dbschema,
+            metadata_get_package_version, metadata_get_package_version_plain_text,
+            metadata_get_service_by_id, metadata_get_services_and_envs, metadata_update_dbschema,
             metadata_update_pipeline_status, MetadataCreateDbschemaParams,
-            MetadataCreateOrUpdatePackageParams, MetadataGetDbschemasAndTablesParams,
-            MetadataGetPackageVersionParams, MetadataGetPackageVersionPlainTextParams,
-            MetadataGetServiceByIdParams, MetadataGetServicesAndEnvsParams,
-            MetadataGetUserPackagesParams, MetadataUpdateDbschemaParams,
+            MetadataCreateOrUpdatePackageParams, MetadataGetPackageVersionParams,
+            MetadataGetPackageVersionPlainTextParams, MetadataGetServiceByIdParams,
+            MetadataGetServicesAndEnvsParams, MetadataUpdateDbschemaParams,
             MetadataUpdatePipelineStatusParams,
         },
     },
@@ -42,9 +39,16 @@ use MetadataService::{
 };
 
 use crate::{
+    dispatch_plan::{DispatchPlan, PlanOutput, PlannedPipeline},
+    environments::{EnvKey, Environments},
+    graph::{load_dependency_graph, Node, NodeKind},
+    orchestrator::{dispatch_and_wait, OrchestrateOptions},
+    pagination::{collect_pages, PAGE_SIZE},
+    pipeline_filter::PipelineFilter,
+    pipeline_provider::{quick_links_with_pipeline, Pipeline},
     publish::{get_cargo_toml_info, get_pyproject_toml_info},
     refresher::update_python_internal_dependency,
-    Environment,
+    services_toml::{read_committed_config, read_services_config, write_service_config_preserving},
 };
 
 fn extract_org_and_package(input: &str) -> Option<(String, String)> {
@@ -68,12 +72,12 @@ pub async fn update_pipeline(
     package_path: &Path,
     metadata_config: &MetadataConfiguration,
     config_path: &Path,
-    env: Environment,
+    env: EnvKey,
     status: String,
 ) {
     let metadata_details = read_package_metadata_file(package_path).unwrap();
 
-    let links_str = serde_json::to_string(&metadata_details.links).unwrap();
+    let links_str = quick_links_with_pipeline(&metadata_details.links, package_path);
 
     let (mut name, version, description, organization, internal_dependencies) =
         match metadata_details.lang {
@@ -92,7 +96,7 @@ pub async fn update_pipeline(
             LANG::Shell => todo!(),
         };
 
-    let services_config = match read_service_config_file(config_path) {
+    let services_config = match read_committed_config(config_path) {
         Ok(c) => c,
         Err(e) => {
             println!("{:?}", e);
@@ -166,7 +170,7 @@ pub async fn register_db(metadata_config: &MetadataConfiguration, releaser_path:
             Some(id) if !id.is_empty() => {
                 // If db.id exists and is not an empty string
                 println!("Database '{}' has ID: {}", db.name, id);
-                
+
                 match metadata_update_dbschema(
                     &metadata_config,
                     MetadataUpdateDbschemaParams {
@@ -213,8 +217,8 @@ pub async fn register_db(metadata_config: &MetadataConfiguration, releaser_path:
                             repo_origin: releaser_config.clone().settings.git_url_prefix.unwrap(),
                             version: releaser_config.version.formatted(),
                             quick_links: Some(Some(serde_json::to_string(&db.links).unwrap())),
-                            // can you add the field schema here in this struct , the value is the 
-                            schema: Some(schema_content)
+                            // can you add the field schema here in this struct , the value is the
+                            schema: Some(schema_content),
                         },
                     },
                 )
@@ -239,14 +243,14 @@ pub async fn register_package(
     package_path: &Path,
     metadata_config: &MetadataConfiguration,
     config_path: &Path,
-    env: Environment,
+    env: EnvKey,
     releaser_path: &Path,
 ) {
     let metadata_details = read_package_metadata_file(package_path).unwrap();
 
-    let links_str = serde_json::to_string(&metadata_details.links).unwrap();
+    let links_str = quick_links_with_pipeline(&metadata_details.links, package_path);
 
-    let services_config = match read_service_config_file(config_path) {
+    let services_config = match read_committed_config(config_path) {
         Ok(c) => c,
         Err(e) => {
             println!("{:?}", e);
@@ -324,87 +328,11 @@ pub async fn register_package(
     };
 }
 
-fn find_pipelines_to_trigger(
-    dependencies_map: &HashMap<String, Vec<String>>,
-    pivot_package: &String,
-    triggered_set: &mut HashSet<String>,
-) -> Vec<String> {
-    let mut result = Vec::new();
-
-    // Find all packages that directly depend on the pivot_package
-    for (package, dependencies) in dependencies_map {
-        // Skip if there's a mutual dependency between pivot_package and package
-        let package_depends_on_pivot = dependencies.contains(pivot_package);
-        let pivot_depends_on_package = dependencies_map
-            .get(pivot_package)
-            .map_or(false, |deps| deps.contains(package));
-
-        if package_depends_on_pivot && pivot_depends_on_package {
-            // Skip adding this package because of mutual dependency
-            continue;
-        }
-
-        if package_depends_on_pivot {
-            // Check if any of the dependencies of this package are already in the triggered set
-            let has_triggered_dependency =
-                dependencies.iter().any(|dep| triggered_set.contains(dep));
-
-            // Only add the package if none of its dependencies are in the triggered set
-            if !has_triggered_dependency {
-                // Recursively find sub-dependencies to add
-                let sub_dependencies =
-                    find_pipelines_to_trigger(dependencies_map, package, triggered_set);
-
-                // Add the current package to the result
-                result.push(package.clone());
-
-                // Add the sub-dependencies to the result
-                result.extend(sub_dependencies);
-            }
-        }
-    }
-
-    // Add the pivot_package to the triggered set
-    triggered_set.insert(pivot_package.clone());
-
-    // Filter out packages that have dependencies which are already in the result
-    let filtered_result: Vec<String> = result
-        .clone()
-        .into_iter()
-        .filter(|pkg| {
-            let binding = vec![];
-            let deps = dependencies_map.get(pkg).unwrap_or(&binding);
-            !deps.iter().any(|dep| result.contains(dep))
-        })
-        .collect();
-
-    // Return the filtered result without duplicates
-    filtered_result
-        .into_iter()
-        .collect::<HashSet<_>>()
-        .into_iter()
-        .collect()
-}
-
-fn find_entry_points_to_trigger(dependencies_map: &HashMap<String, Vec<String>>) -> Vec<String> {
-    let mut result = Vec::new();
-
-    // Iterate through the HashMap
-    for (key, value) in dependencies_map {
-        // Check if the Vec<String> has a length of 0
-        if value.is_empty() {
-            result.push(key.clone()); // Add the key to the result if the Vec is empty
-        }
-    }
-
-    result // Return the result vector
-}
-
 pub async fn refresh_internal_dependency_versions(
     config_path: &Path,
     metadata_config: &MetadataConfiguration,
 ) {
-    let config = read_service_config_file(config_path).unwrap();
+    let config = read_services_config(config_path).unwrap();
 
     let (mut current_package_name, version, description, organization, internal_dependencies) =
         match config.lang {
@@ -541,158 +469,199 @@ pub fn gen_ist(package_path: &Path, jwt_token: &String) {
     println!("{:?}", token);
 }
 
-pub async fn system_check(
-    config_path: &Path,
-    iam_config: &IAMConfiguration,
-    metadata_config: &MetadataConfiguration,
-    pipeline_token: &String,
-) {
-    let config = read_service_config_file(config_path).unwrap();
-
-    let mut dependencies_map: HashMap<String, Vec<String>> = HashMap::new();
-
-    let mut repo_details_map: HashMap<String, Option<(String, String)>> = HashMap::new();
-
-    let mut repo_type_map: HashMap<String, String> = HashMap::new();
+pub fn report_cycles(cycles: &[Vec<String>]) {
+    for cycle in cycles {
+        let mut path = cycle.clone();
+        path.push(cycle[0].clone());
+        eprintln!(
+            "{} {}, its pipelines are not triggered",
+            "Dependency cycle:".red(),
+            path.join(" -> ")
+        );
+    }
+}
 
-    match metadata_get_user_packages(
-        metadata_config,
-        MetadataGetUserPackagesParams {
-            org_id: config.organization_id.clone(),
-            env: "stage".to_string(),
+/// Updates the status of the pipeline of the node in Metadata (`waiting`, `running`, `passing`
+/// or `failing`). Returns whether it was updated.
+pub async fn set_pipeline_status(
+    metadata_config: &MetadataConfiguration,
+    node: &Node,
+    env: &EnvKey,
+    status: &str,
+) -> bool {
+    let (org, pkg) = extract_org_and_package(&node.slug).unwrap();
+    match metadata_update_pipeline_status(
+        &metadata_config,
+        MetadataUpdatePipelineStatusParams {
+            pipeline_status_update_request: {
+                PipelineStatusUpdateRequest {
+                    env: env.to_string(),
+                    status: status.to_string(),
+                    update_type: node.kind.update_type().to_string(),
+                    org_id: org,
+                    identifier: pkg,
+                }
+            },
         },
     )
     .await
     {
-        Ok(packages) => {
-            for pkg in packages {
-                let key = format!(
-                    "@{}/{}",
-                    config.organization_id.clone(),
-                    pkg.identifier.clone()
-                );
+        Ok(status) => {
+            println!("{:?}", status);
+            true
+        }
+        Err(e) => {
+            println!("Error calling metadata_update_pipeline_status{:?}", e);
+            false
+        }
+    }
+}
 
-                repo_details_map.insert(
-                    key.clone(),
-                    extract_username_and_repo(&pkg.repo_origin.unwrap().unwrap()),
+/// Dispatches the pipeline of the node on its CI provider and marks it as waiting.
+/// Returns whether the pipeline was dispatched.
+pub async fn dispatch_pipeline(
+    client: &Client,
+    metadata_config: &MetadataConfiguration,
+    pipeline_token: &String,
+    node: &Node,
+    env: &EnvKey,
+) -> bool {
+    let pipeline = &node.slug;
+    if let Some(target) = &node.pipeline {
+        println!(
+            "Dispatching {} on {}@{}",
+            target.config.provider,
+            target.repo,
+            target.config.branch(env)
+        );
+
+        let response = target
+            .provider()
+            .dispatch_request(client, &target.token(pipeline_token), env)
+            .send()
+            .await;
+
+        match response {
+            Ok(resp) if resp.status().is_success() => {
+                println!("Workflow dispatched for pipeline: {}", pipeline);
+                if set_pipeline_status(metadata_config, node, env, "waiting").await {
+                    sleep(Duration::from_secs(5)).await;
+                }
+                true
+            }
+            Ok(resp) => {
+                eprintln!(
+                    "Failed to dispatch workflow for pipeline {}: Status Code: {} {:?}",
+                    pipeline,
+                    resp.status(),
+                    resp
                 );
-
-                repo_type_map.insert(key.clone(), "package".to_string());
-
-                dependencies_map.insert(key.clone(), pkg.dependencies.clone());
+                false
+            }
+            Err(e) => {
+                eprintln!(
+                    "Error occurred while dispatching workflow for pipeline {}: {:?}",
+                    pipeline, e
+                );
+                false
             }
         }
-        Err(_) => todo!(),
+    } else {
+        eprintln!(
+            "No pipeline found for {}, its repo origin is unknown or needs a [pipeline] section in metadata.toml",
+            pipeline
+        );
+        false
     }
+}
 
-    match metadata_get_services_and_envs(
-        metadata_config,
-        MetadataGetServicesAndEnvsParams {
-            page_number: Some("1".to_string()),
-            page_size: Some("50".to_string()),
-            org_id: config.organization_id.clone(),
-        },
-    )
-    .await
-    {
-        Ok(services) => {
-            for service in services {
-                let key = format!(
-                    "@{}/{}",
-                    config.organization_id.clone(),
-                    service.identifier.clone()
-                );
-
-                dependencies_map.insert(key.clone(), service.dependencies.clone());
-
-                repo_details_map.insert(
-                    key.clone(),
-                    extract_username_and_repo(&service.repo_origin.unwrap().unwrap()),
-                );
-                repo_type_map.insert(key.clone(), "service".to_string());
+/// Dispatches the pipelines of `nodes` once per run, the nodes sharing an already dispatched
+/// run (see `Pipeline::run_key`) are only marked as waiting
+pub async fn dispatch_pipelines(
+    client: &Client,
+    metadata_config: &MetadataConfiguration,
+    pipeline_token: &String,
+    nodes: &[&Node],
+    env: &EnvKey,
+) {
+    let mut dispatched: BTreeMap<String, String> = BTreeMap::new();
+    for node in nodes {
+        let run_key = node.pipeline.as_ref().map(|pipeline| pipeline.run_key(env));
+        if let Some(slug) = run_key.as_ref().and_then(|key| dispatched.get(key)) {
+            println!("{} shares the pipeline of {}", node.slug, slug);
+            set_pipeline_status(metadata_config, node, env, "waiting").await;
+            continue;
+        }
+        if dispatch_pipeline(client, metadata_config, pipeline_token, node, env).await {
+            if let Some(key) = run_key {
+                dispatched.insert(key, node.slug.clone());
             }
         }
-        Err(_) => todo!(),
     }
+}
 
-    let pipelines = find_entry_points_to_trigger(&dependencies_map);
+/// Triggers the pipelines without dependencies, DB schemas, caches and queues included, each
+/// of them triggers its dependents once done
+pub async fn system_check(
+    config_path: &Path,
+    iam_config: &IAMConfiguration,
+    metadata_config: &MetadataConfiguration,
+    pipeline_token: &String,
+    env: &EnvKey,
+    dry_run: Option<PlanOutput>,
+) {
+    let config = read_services_config(config_path).unwrap();
 
-    println!("Pipelines : {:?}", pipelines);
+    let environments = Environments::load(config_path);
+    if let Err(e) = environments.ensure_known(env) {
+        eprintln!("{}", e);
+        exit(1);
+    }
 
-    let client = Client::new();
-    for pipeline in pipelines {
-        if let Some((repo_owner, repo_name)) =
-            repo_details_map.get(&pipeline).and_then(|x| x.clone())
-        {
-            // Set the request URL for dispatching the workflow
-            let url = format!(
-                "https://api.github.com/repos/{}/{}/actions/workflows/CI.yml/dispatches",
-                repo_owner, repo_name
-            );
+    let graph = match load_dependency_graph(metadata_config, &config.organization_id, env).await {
+        Ok(graph) => graph,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
 
-            // Prepare the JSON body
-            let body = json!({
-                "ref": "main" // Specify the branch to trigger the workflow
-            });
-
-            println!("URL : {}", url);
-
-            // Make the POST request
-            let response = client
-                .post(&url)
-                .header("Accept", "application/vnd.github+json")
-                .header("User-Agent", "ginger-connector") // Added User-Agent header
-                .header("Authorization", format!("Bearer {}", pipeline_token))
-                .json(&body)
-                .send()
-                .await;
-
-            let (org, pkg) = extract_org_and_package(&pipeline).unwrap();
-            match response {
-                Ok(resp) if resp.status().is_success() => {
-                    println!("Workflow dispatched for pipeline: {}", pipeline);
-                    match metadata_update_pipeline_status(
-                        &metadata_config,
-                        MetadataUpdatePipelineStatusParams {
-                            pipeline_status_update_request: {
-                                PipelineStatusUpdateRequest {
-                                    env: "stage".to_string(),
-                                    status: "waiting".to_string(),
-                                    update_type: repo_type_map.get(&pipeline).unwrap().to_string(),
-                                    org_id: org,
-                                    identifier: pkg,
-                                }
-                            },
-                        },
-                    )
-                    .await
-                    {
-                        Ok(status) => {
-                            println!("{:?}", status);
-                            sleep(Duration::from_secs(5)).await;
-                        }
-                        Err(e) => {
-                            println!("Error calling metadata_update_pipeline_status{:?}", e);
-                        }
-                    }
-                }
-                Ok(resp) => {
-                    eprintln!(
-                        "Failed to dispatch workflow for pipeline {}: Status Code: {} {:?}",
-                        pipeline,
-                        resp.status(),
-                        resp
-                    );
-                }
-                Err(e) => eprintln!(
-                    "Error occurred while dispatching workflow for pipeline {}: {:?}",
-                    pipeline, e
-                ),
+    let plan = graph.layers();
+
+    if let Some(output) = dry_run {
+        let mut dispatch_plan = DispatchPlan::new();
+        for pipeline in plan.layers.first().into_iter().flatten() {
+            if let Some(node) = graph.get(pipeline) {
+                dispatch_plan.dispatch(PlannedPipeline::new(node, env, 1, vec![pipeline.clone()]));
             }
-        } else {
-            eprintln!("Repo details not found for pipeline: {}", pipeline);
         }
+        for pipeline in plan.layers.iter().skip(1).flatten() {
+            dispatch_plan.skip(pipeline, "triggered by the pipelines of its dependencies");
+        }
+        for pipeline in plan.cycles.iter().flatten() {
+            dispatch_plan.skip(pipeline, "part of a dependency cycle");
+        }
+        for pipeline in &plan.blocked {
+            dispatch_plan.skip(pipeline, "blocked by a dependency cycle");
+        }
+        dispatch_plan.print(output);
+        return;
+    }
+
+    report_cycles(&plan.cycles);
+    if !plan.blocked.is_empty() {
+        eprintln!("Blocked by a dependency cycle : {:?}", plan.blocked);
     }
+
+    let pipelines = plan.layers.first().cloned().unwrap_or_default();
+
+    println!("Pipelines : {:?}", pipelines);
+
+    let nodes: Vec<&Node> = pipelines
+        .iter()
+        .filter_map(|pipeline| graph.get(pipeline))
+        .collect();
+    dispatch_pipelines(&Client::new(), metadata_config, pipeline_token, &nodes, env).await;
 }
 
 pub async fn trigger_pipeline(
@@ -701,8 +670,11 @@ pub async fn trigger_pipeline(
     metadata_config: &MetadataConfiguration,
     pipeline_token: &String,
     id: &String,
+    env: &EnvKey,
+    dry_run: Option<PlanOutput>,
+    wait: Option<OrchestrateOptions>,
 ) {
-    let config = read_service_config_file(config_path).unwrap();
+    let config = read_services_config(config_path).unwrap();
 
     let (mut current_package_name, version, description, organization, internal_dependencies) =
         match config.lang {
@@ -721,6 +693,12 @@ pub async fn trigger_pipeline(
             LANG::Shell => todo!(),
         };
 
+    let environments = Environments::load(config_path);
+    if let Err(e) = environments.ensure_known(env) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+
     match metadata_get_service_by_id(
         &metadata_config,
         MetadataGetServiceByIdParams {
@@ -731,74 +709,33 @@ pub async fn trigger_pipeline(
     .await
     {
         Ok(service) => {
-            if let Some((repo_owner, repo_name)) =
-                extract_username_and_repo(&service.repo_origin.unwrap().unwrap())
-            {
-                // Set the request URL for dispatching the workflow
-                let url = format!(
-                    "https://api.github.com/repos/{}/{}/actions/workflows/CI.yml/dispatches",
-                    repo_owner, repo_name
-                );
+            let node = Node {
+                slug: format!("@{}/{}", organization, id),
+                kind: NodeKind::Service,
+                pipeline: Pipeline::resolve(
+                    service.repo_origin.clone().flatten().as_deref(),
+                    service.quick_links.clone().flatten().as_deref(),
+                ),
+                dependencies: vec![],
+                status: None,
+            };
 
-                // Prepare the JSON body
-                let body = json!({
-                    "ref": "main" // Specify the branch to trigger the workflow
-                });
-                let client = Client::new();
-
-                // Make the POST request
-                let response = client
-                    .post(&url)
-                    .header("Accept", "application/vnd.github+json")
-                    .header("User-Agent", "ginger-connector") // Added User-Agent header
-                    .header("Authorization", format!("Bearer {}", pipeline_token))
-                    .json(&body)
-                    .send()
-                    .await;
-
-                match response {
-                    Ok(resp) if resp.status().is_success() => {
-                        println!("Workflow dispatched for pipeline: {}", id);
-                        match metadata_update_pipeline_status(
-                            &metadata_config,
-                            MetadataUpdatePipelineStatusParams {
-                                pipeline_status_update_request: {
-                                    PipelineStatusUpdateRequest {
-                                        env: "stage".to_string(),
-                                        status: "waiting".to_string(),
-                                        update_type: "service".to_string(),
-                                        org_id: organization,
-                                        identifier: id.to_string(),
-                                    }
-                                },
-                            },
-                        )
-                        .await
-                        {
-                            Ok(status) => {
-                                println!("{:?}", status);
-                                sleep(Duration::from_secs(5)).await;
-                            }
-                            Err(e) => {
-                                println!("Error calling metadata_update_pipeline_status{:?}", e);
-                            }
-                        }
-                    }
-                    Ok(resp) => {
-                        eprintln!(
-                            "Failed to dispatch workflow for pipeline {}: Status Code: {} {:?}",
-                            id,
-                            resp.status(),
-                            resp
-                        );
-                    }
-                    Err(e) => eprintln!(
-                        "Error occurred while dispatching workflow for pipeline {}: {:?}",
-                        id, e
-                    ),
+            if let Some(output) = dry_run {
+                let mut dispatch_plan = DispatchPlan::new();
+                let path = vec![node.slug.clone()];
+                dispatch_plan.dispatch(PlannedPipeline::new(&node, env, 1, path));
+                dispatch_plan.print(output);
+                return;
+            }
+
+            match wait {
+                Some(options) => {
+                    dispatch_and_wait(metadata_config, pipeline_token, &[&node], options).await
+                }
+                None => {
+                    dispatch_pipeline(&Client::new(), metadata_config, pipeline_token, &node, env)
+                        .await;
                 }
-            } else {
-                eprintln!("Repo details not found for pipeline: {}", id);
             }
         }
         Err(e) => {
@@ -812,9 +749,12 @@ pub async fn fetch_dependent_pipelines(
     iam_config: &IAMConfiguration,
     metadata_config: &MetadataConfiguration,
     pipeline_token: &String,
-    pipeline_ids_to_skip: Vec<String>,
+    filter: PipelineFilter,
+    env: &EnvKey,
+    dry_run: Option<PlanOutput>,
+    wait: Option<OrchestrateOptions>,
 ) {
-    let config = read_service_config_file(config_path).unwrap();
+    let config = read_services_config(config_path).unwrap();
 
     let (mut current_package_name, version, description, organization, internal_dependencies) =
         match config.lang {
@@ -838,226 +778,231 @@ pub async fn fetch_dependent_pipelines(
         config.organization_id, current_package_name
     );
 
-    let mut dependencies_map: HashMap<String, Vec<String>> = HashMap::new();
-
-    let mut repo_details_map: HashMap<String, Option<(String, String)>> = HashMap::new();
-
-    let mut repo_type_map: HashMap<String, String> = HashMap::new();
+    let pivot = format!(
+        "@{}/{}",
+        config.organization_id.clone(),
+        current_package_name.clone()
+    );
 
-    match metadata_get_user_packages(
-        metadata_config,
-        MetadataGetUserPackagesParams {
-            org_id: config.organization_id.clone(),
-            env: "stage".to_string(),
-        },
-    )
-    .await
-    {
-        Ok(packages) => {
-            for pkg in packages {
-                let key = format!(
-                    "@{}/{}",
-                    config.organization_id.clone(),
-                    pkg.identifier.clone()
-                );
+    let environments = Environments::load(config_path);
+    if let Err(e) = environments.ensure_known(env) {
+        eprintln!("{}", e);
+        exit(1);
+    }
 
-                repo_details_map.insert(
-                    key.clone(),
-                    extract_username_and_repo(&pkg.repo_origin.unwrap().unwrap()),
-                );
+    let graph = match load_dependency_graph(metadata_config, &config.organization_id, env).await {
+        Ok(graph) => graph,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
 
-                repo_type_map.insert(key.clone(), "package".to_string());
+    // Dependents in a cycle with this package would trigger it back, endlessly
+    let mut downstream = graph.downstream_of(&pivot);
+    let mut scope = downstream.clone();
+    scope.insert(pivot.clone());
+    let cycles: Vec<Vec<String>> = graph
+        .cycles_in(&scope)
+        .into_iter()
+        .filter(|cycle| cycle.contains(&pivot))
+        .collect();
+    for cycle in &cycles {
+        for slug in cycle {
+            downstream.remove(slug);
+        }
+    }
 
-                dependencies_map.insert(key.clone(), pkg.dependencies.clone());
+    let filtered_out = filter.apply(&graph, &mut downstream);
+
+    // Only the dependents whose dependencies are all up to date are triggered now, the others
+    // are triggered by the pipelines of their dependencies
+    let plan = graph.layers_of(&downstream);
+    let pipelines = plan.layers.first().cloned().unwrap_or_default();
+
+    if let Some(output) = dry_run {
+        let mut dispatch_plan = DispatchPlan::new();
+        for pipeline in &pipelines {
+            if let Some(node) = graph.get(pipeline) {
+                let path = graph
+                    .path_between(&pivot, pipeline)
+                    .unwrap_or_else(|| vec![pipeline.clone()]);
+                dispatch_plan.dispatch(PlannedPipeline::new(node, env, 1, path));
+            }
+        }
+        for pipeline in plan.layers.iter().skip(1).flatten() {
+            dispatch_plan.skip(pipeline, "triggered by the pipelines of its dependencies");
+        }
+        for (pipeline, reason) in &filtered_out {
+            dispatch_plan.skip(pipeline, reason.clone());
+        }
+        for pipeline in cycles.iter().chain(plan.cycles.iter()).flatten() {
+            if *pipeline != pivot {
+                dispatch_plan.skip(pipeline, "part of a dependency cycle");
             }
         }
-        Err(_) => todo!(),
+        for pipeline in &plan.blocked {
+            dispatch_plan.skip(pipeline, "blocked by a dependency cycle");
+        }
+        dispatch_plan.print(output);
+        return;
     }
 
-    match metadata_get_dbschemas_and_tables(
-        metadata_config,
-        MetadataGetDbschemasAndTablesParams {
-            org_id: config.organization_id.clone(),
-            env: "stage".to_string(),
-        },
-    )
-    .await
-    {
-        Ok(schemas) => {
-            match metadata_get_services_and_envs(
-                metadata_config,
-                MetadataGetServicesAndEnvsParams {
-                    page_number: Some("1".to_string()),
-                    page_size: Some("50".to_string()),
-                    org_id: config.organization_id.clone(),
-                },
-            )
-            .await
-            {
-                Ok(services) => {
-                    for service in services {
-                        let mut dependencies = service.dependencies.clone();
-
-                        for schema in schemas.clone() {
-                            if schema.identifier == service.db_schema_id
-                                || schema.identifier == service.message_queue_schema_id
-                                || schema.identifier == service.cache_schema_id
-                            {
-                                dependencies.push(format!(
-                                    "@{}/{}",
-                                    config.organization_id.clone(),
-                                    schema.name
-                                ));
-                            }
-                        }
+    report_cycles(&cycles);
+    report_cycles(&plan.cycles);
+    for (pipeline, reason) in &filtered_out {
+        println!("Skipping pipeline : {:?}, {}", pipeline, reason);
+    }
 
-                        let key = format!(
-                            "@{}/{}",
-                            config.organization_id.clone(),
-                            service.identifier.clone()
-                        );
+    println!("Pipelines : {:?}", pipelines);
 
-                        dependencies_map.insert(key.clone(), dependencies);
+    let nodes: Vec<&Node> = pipelines
+        .iter()
+        .filter_map(|pipeline| graph.get(pipeline))
+        .collect();
 
-                        repo_details_map.insert(
-                            key.clone(),
-                            extract_username_and_repo(&service.repo_origin.unwrap().unwrap()),
-                        );
-                        repo_type_map.insert(key.clone(), "service".to_string());
-                    }
-                }
-                Err(_) => todo!(),
-            }
+    match wait {
+        Some(options) => dispatch_and_wait(metadata_config, pipeline_token, &nodes, options).await,
+        None => {
+            dispatch_pipelines(&Client::new(), metadata_config, pipeline_token, &nodes, env).await
         }
-        Err(_) => todo!(),
     }
+}
 
-    let mut triggered_set = HashSet::new();
-    let pipelines = find_pipelines_to_trigger(
-        &dependencies_map,
-        &format!(
-            "@{}/{}",
-            config.organization_id.clone(),
-            current_package_name.clone()
-        ),
-        &mut triggered_set,
-    );
+/// How `config` changes the selected dependencies
+pub enum ConfigSelection {
+    /// Check / uncheck them in a multi select
+    Interactive,
+    Add(Vec<String>),
+    Remove(Vec<String>),
+    /// Keep the selection, only refresh the urls
+    Sync,
+}
 
-    println!("Pipelines : {:?}", pipelines);
+/// Services and portals currently selected in services.toml
+fn selected_dependencies(config: &ServiceConfig) -> Vec<String> {
+    let mut selected: Vec<String> = config
+        .services
+        .iter()
+        .chain(config.portals_refs.iter())
+        .flat_map(|refs| refs.keys().cloned())
+        .collect();
+    selected.sort();
+    selected.dedup();
+    selected
+}
 
-    let client = Client::new();
-    for pipeline in pipelines {
-        let pipeline_id_parts: Vec<&str> = pipeline.split("/").collect();
+fn choose_dependencies(
+    selection: &ConfigSelection,
+    available: &[String],
+    current: &[String],
+) -> Vec<String> {
+    let unknown = |names: &[String]| -> Vec<String> {
+        names
+            .iter()
+            .filter(|name| !available.contains(name))
+            .cloned()
+            .collect()
+    };
 
-        if pipeline_ids_to_skip.contains(&pipeline_id_parts.get(1).unwrap_or(&"").to_string()) {
-            println!("Skipping pipeline : {:?}", pipeline);
-            continue;
-        }
-        if let Some((repo_owner, repo_name)) =
-            repo_details_map.get(&pipeline).and_then(|x| x.clone())
-        {
-            // Set the request URL for dispatching the workflow
-            let url = format!(
-                "https://api.github.com/repos/{}/{}/actions/workflows/CI.yml/dispatches",
-                repo_owner, repo_name
-            );
+    match selection {
+        ConfigSelection::Interactive => {
+            let service_selector_validator = |_a: &[ListOption<&String>]| Ok(Validation::Valid);
 
-            // Prepare the JSON body
-            let body = json!({
-                "ref": "main" // Specify the branch to trigger the workflow
-            });
-
-            // Make the POST request
-            let response = client
-                .post(&url)
-                .header("Accept", "application/vnd.github+json")
-                .header("User-Agent", "ginger-connector") // Added User-Agent header
-                .header("Authorization", format!("Bearer {}", pipeline_token))
-                .json(&body)
-                .send()
-                .await;
-
-            let (org, pkg) = extract_org_and_package(&pipeline).unwrap();
-            match response {
-                Ok(resp) if resp.status().is_success() => {
-                    println!("Workflow dispatched for pipeline: {}", pipeline);
-                    match metadata_update_pipeline_status(
-                        &metadata_config,
-                        MetadataUpdatePipelineStatusParams {
-                            pipeline_status_update_request: {
-                                PipelineStatusUpdateRequest {
-                                    env: "stage".to_string(),
-                                    status: "waiting".to_string(),
-                                    update_type: repo_type_map.get(&pipeline).unwrap().to_string(),
-                                    org_id: org,
-                                    identifier: pkg,
-                                }
-                            },
-                        },
-                    )
-                    .await
-                    {
-                        Ok(status) => {
-                            println!("{:?}", status);
-                            sleep(Duration::from_secs(5)).await;
-                        }
-                        Err(e) => {
-                            println!("Error calling metadata_update_pipeline_status{:?}", e);
-                        }
-                    }
+            let existing_services_namespace: Vec<usize> = available
+                .iter()
+                .enumerate()
+                .filter(|(_, service_name)| current.contains(service_name))
+                .map(|(index, _)| index)
+                .collect();
+
+            let ans = MultiSelect::new(
+                "Select the services you want to add to this project ",
+                available.to_vec(),
+            )
+            .with_validator(service_selector_validator)
+            .with_page_size(20)
+            .with_default(&existing_services_namespace)
+            .prompt();
+
+            ans.unwrap()
+        }
+        ConfigSelection::Add(names) => {
+            let missing = unknown(names);
+            if !missing.is_empty() {
+                eprintln!(
+                    "Unknown services: {}. Run {} to see the available ones",
+                    missing.join(", "),
+                    "ginger-connector services search".blue()
+                );
+                exit(1);
+            }
+            let mut selected = current.to_vec();
+            for name in names {
+                if selected.contains(name) {
+                    println!("{} is already added", name);
+                } else {
+                    selected.push(name.clone());
                 }
-                Ok(resp) => {
-                    eprintln!(
-                        "Failed to dispatch workflow for pipeline {}: Status Code: {} {:?}",
-                        pipeline,
-                        resp.status(),
-                        resp
-                    );
+            }
+            selected
+        }
+        ConfigSelection::Remove(names) => {
+            for name in names {
+                if !current.contains(name) {
+                    println!("{} is not added to this project", name);
                 }
-                Err(e) => eprintln!(
-                    "Error occurred while dispatching workflow for pipeline {}: {:?}",
-                    pipeline, e
-                ),
             }
-        } else {
-            eprintln!("Repo details not found for pipeline: {}", pipeline);
+            current
+                .iter()
+                .filter(|name| !names.contains(name))
+                .cloned()
+                .collect()
+        }
+        ConfigSelection::Sync => {
+            for name in unknown(current) {
+                println!(
+                    "{} is no longer available in the metadata service, keeping its current urls",
+                    name.yellow()
+                );
+            }
+            current.to_vec()
         }
     }
 }
 
-fn extract_username_and_repo(github_url: &str) -> Option<(String, String)> {
-    // Check if the input string starts with the expected GitHub URL pattern
-    if github_url.starts_with("https://github.com/") {
-        // Strip the "https://github.com/" part and split the rest by '/'
-        let parts: Vec<&str> = github_url["https://github.com/".len()..]
-            .split('/')
-            .collect();
-
-        // Ensure there are exactly two parts: username and repo name
-        if parts.len() == 2 {
-            let username = parts[0].to_string();
-            let repo_name = parts[1].to_string();
-            return Some((username, repo_name));
-        }
+/// Carries over the urls of a dependency missing from the metadata service
+fn keep_current_urls(
+    current: &Option<HashMap<String, HashMap<String, String>>>,
+    refs: &mut HashMap<String, HashMap<String, String>>,
+    service_name: &str,
+) {
+    if let Some(urls) = current
+        .as_ref()
+        .and_then(|current| current.get(service_name))
+    {
+        refs.insert(service_name.to_string(), urls.clone());
     }
-    // Return None if the URL is not valid or doesn't match the expected pattern
-    None
 }
 
 pub async fn fetch_metadata_and_process(
     config_path: &Path,
     iam_config: &IAMConfiguration,
     metadata_config: &MetadataConfiguration,
+    selection: ConfigSelection,
 ) {
+    // The committed file as is, without the local overlay or interpolated values, since it is written back
     let mut config = read_service_config_file(config_path).unwrap();
-    match metadata_get_services_and_envs(
-        metadata_config,
-        MetadataGetServicesAndEnvsParams {
-            page_number: Some("1".to_string()),
-            page_size: Some("50".to_string()),
-            org_id: config.organization_id.clone(),
-        },
-    )
+    match collect_pages(|page| {
+        metadata_get_services_and_envs(
+            metadata_config,
+            MetadataGetServicesAndEnvsParams {
+                page_number: Some(page.to_string()),
+                page_size: Some(PAGE_SIZE.to_string()),
+                org_id: config.organization_id.clone(),
+            },
+        )
+    })
     .await
     {
         Ok(services) => {
@@ -1092,56 +1037,14 @@ pub async fn fetch_metadata_and_process(
             println!("Package organization: {}", organization);
             println!("Package description: {}", description);
 
-            let service_selector_validator = |a: &[ListOption<&String>]| {
-                // if a.len() < 1 {
-                //     return Ok(Validation::Invalid(
-                //         "At least one service is required!".into(),
-                //     ));
-                // }
-                Ok(Validation::Valid)
-            };
-
-            let mut existing_services_namespace: Vec<usize> = vec![];
-
             let service_names: Vec<String> = services
                 .iter()
                 .filter(|s| s.identifier != current_package_name)
                 .map(|s| format!("@{}/{}", s.organization_id.clone(), s.identifier.clone()))
                 .collect();
 
-            // Collect default selections for both services and portals
-            for (index, service_name) in service_names.iter().enumerate() {
-                if config
-                    .services
-                    .as_ref()
-                    .map_or(false, |s| s.contains_key(service_name))
-                {
-                    existing_services_namespace.push(index);
-                } else if config
-                    .portals_refs
-                    .as_ref()
-                    .map_or(false, |p| p.contains_key(service_name))
-                {
-                    existing_services_namespace.push(index);
-                }
-            }
-
-            let service_names: Vec<String> = services
-                .iter()
-                .filter(|s| s.identifier != current_package_name)
-                .map(|s| format!("@{}/{}", s.organization_id.clone(), s.identifier.clone()))
-                .collect();
-
-            let ans = MultiSelect::new(
-                "Select the services you want to add to this project ",
-                service_names.clone(),
-            )
-            .with_validator(service_selector_validator)
-            .with_page_size(20)
-            .with_default(&existing_services_namespace)
-            .prompt();
-
-            let selected_services = ans.unwrap();
+            let selected_services =
+                choose_dependencies(&selection, &service_names, &selected_dependencies(&config));
             // println!("{:?}", selected_services);
             let mut new_services = HashMap::new();
 
@@ -1191,6 +1094,10 @@ pub async fn fetch_metadata_and_process(
                             );
                         }
                     }
+                } else {
+                    keep_current_urls(&config.services, &mut new_services, service_name);
+                    keep_current_urls(&config.portals_refs, &mut new_portal_refs, service_name);
+                    keep_current_urls(&config.ws_refs, &mut new_ws_refs, service_name);
                 }
             }
             println!("{:?}", new_services);
@@ -1198,7 +1105,7 @@ pub async fn fetch_metadata_and_process(
             config.portals_refs = Some(new_portal_refs);
             config.ws_refs = Some(new_ws_refs);
 
-            match write_service_config_file(config_path, &config) {
+            match write_service_config_preserving(config_path, &config) {
                 Ok(_) => println!("Configuration updated successfully"),
                 Err(_) => println!("Could not save the config file. Please check if you have appropriate permission to write"),
             };
@@ -1210,3 +1117,38 @@ pub async fn fetch_metadata_and_process(
         }
     };
 }
+
+/// Prints the dependencies selected in services.toml along with their env urls
+pub fn list_dependencies(config_path: &Path) {
+    let config = read_service_config_file(config_path).unwrap_or_else(|e| {
+        println!("{:?}", e);
+        println!(
+            "There is no service configuration found. Please use {} to add one. Exiting",
+            "ginger-connector init".blue()
+        );
+        exit(1);
+    });
+
+    let sections = [
+        ("Services", &config.services),
+        ("Portals", &config.portals_refs),
+        ("Websockets", &config.ws_refs),
+    ];
+    for (title, refs) in sections {
+        let refs = match refs {
+            Some(refs) if !refs.is_empty() => refs,
+            _ => continue,
+        };
+        println!("{}:", title.bold());
+        let mut names: Vec<&String> = refs.keys().collect();
+        names.sort();
+        for name in names {
+            println!("  {}", name);
+            let mut envs: Vec<(&String, &String)> = refs[name].iter().collect();
+            envs.sort();
+            for (env, url) in envs {
+                println!("    {} : {}", env, url);
+            }
+        }
+    }
+}